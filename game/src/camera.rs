@@ -1,5 +1,22 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, window::PrimaryWindow};
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use serde::{Deserialize, Serialize};
+
+use crate::{cinematic::camera_path_playing, orders::{order_hotkeys_active, OrderTargeting}, selection::{SelectionBoxInProcess, SelectionLassoInProcess}, terrain::TerrainHeightmap, units::{Selected, SelectedUnits}};
+
+/// Panning speed (world units per second) per unit of the camera distance from its focus
+const PAN_SPEED: f32 = 2.0;
+const MIN_ZOOM_RADIUS: f32 = 5.0;
+/// Far enough to get into the strategic view
+const MAX_ZOOM_RADIUS: f32 = 100.0;
+/// Lowest camera angle (radians above the horizon), so the view doesn't turn into a first-person one
+const MIN_PITCH: f32 = 0.5;
+/// How quickly the focus height catches up with the terrain under it, the higher the faster
+const FOCUS_HEIGHT_SMOOTHNESS: f32 = 6.0;
+/// Ctrl+key stores the view, the key alone brings it back
+const BOOKMARK_KEYS: [KeyCode; 4] = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+const LAST_ALERT_KEY: KeyCode = KeyCode::Backspace;
+const FOLLOW_KEY: KeyCode = KeyCode::KeyC;
 
 pub struct MyCameraPlugin;
 
@@ -7,8 +24,91 @@ impl Plugin for MyCameraPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins(PanOrbitCameraPlugin)
+            .add_event::<CenterCamera>()
+            .add_event::<Alert>()
+            .init_resource::<EdgeScrolling>()
+            .init_resource::<CameraBookmarks>()
+            .init_resource::<LastAlert>()
+            .init_resource::<CameraFollow>()
             .add_systems(Startup, setup_camera)
-            .add_systems(Update, camera_keyboard_controls);
+            // The camera is left alone while a cinematic path plays
+            .add_systems(Update, camera_keyboard_controls.run_if(not(camera_path_playing)))
+            .add_systems(Update, camera_edge_scrolling.run_if(not(camera_path_playing)))
+            .add_systems(Update, camera_bookmarks.run_if(not(camera_path_playing)))
+            .add_systems(Update, remember_alerts)
+            .add_systems(Update, jump_to_alert.after(remember_alerts).before(center_camera).before(follow_selection).run_if(not(camera_path_playing)))
+            .add_systems(Update, center_camera.run_if(not(camera_path_playing)))
+            .add_systems(Update, toggle_camera_follow.run_if(not(camera_path_playing)))
+            .add_systems(Update, follow_selection.after(toggle_camera_follow).after(camera_keyboard_controls).after(camera_edge_scrolling).after(camera_bookmarks).run_if(not(camera_path_playing)))
+            .add_systems(Update, constrain_camera.after(follow_selection).after(center_camera).run_if(not(camera_path_playing)));
+    }
+}
+
+/// Moves the camera focus to the given point, keeping the angle and zoom
+#[derive(Event, Debug, Clone)]
+pub struct CenterCamera(pub Vec3);
+
+/// Something the player should look at, e.g. own units coming under fire
+#[derive(Event, Debug, Clone)]
+pub struct Alert(pub Vec3);
+
+#[derive(Resource, Default, Debug)]
+struct LastAlert(Option<Vec3>);
+
+/// Where the camera looks and from which angle and distance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraView {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub radius: f32,
+}
+
+impl CameraView {
+    /// The view the camera is heading to
+    pub fn target_of(pan_orbit: &PanOrbitCamera) -> CameraView {
+        CameraView {
+            focus: pan_orbit.target_focus,
+            yaw: pan_orbit.target_yaw,
+            pitch: pan_orbit.target_pitch,
+            radius: pan_orbit.target_radius,
+        }
+    }
+
+    /// Makes the camera move to the view, smoothly
+    pub fn set_target(&self, pan_orbit: &mut PanOrbitCamera) {
+        pan_orbit.target_focus = self.focus;
+        pan_orbit.target_yaw = self.yaw;
+        pan_orbit.target_pitch = self.pitch;
+        pan_orbit.target_radius = self.radius;
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+struct CameraBookmarks {
+    /// See [`BOOKMARK_KEYS`]
+    slots: [Option<CameraView>; BOOKMARK_KEYS.len()],
+}
+
+/// While enabled, the camera focus stays on the selected units, orbiting and zooming still work.
+/// Panning by hand switches it off.
+#[derive(Resource, Default, Debug)]
+pub struct CameraFollow {
+    pub enabled: bool,
+}
+
+/// Panning the camera by moving the cursor to the window border
+#[derive(Resource, Debug)]
+pub struct EdgeScrolling {
+    pub enabled: bool,
+    /// Distance from the border (logical pixels) at which panning starts,
+    /// the speed grows from zero there to full speed at the border itself
+    pub margin: f32,
+}
+
+impl Default for EdgeScrolling {
+    fn default() -> Self {
+        EdgeScrolling { enabled: true, margin: 20.0 }
     }
 }
 
@@ -21,49 +121,225 @@ fn setup_camera(mut commands: Commands,) {
         PanOrbitCamera {
             button_orbit: MouseButton::Middle,
             button_pan: MouseButton::Other(999),
+            zoom_lower_limit: MIN_ZOOM_RADIUS,
+            zoom_upper_limit: Some(MAX_ZOOM_RADIUS),
+            pitch_lower_limit: Some(MIN_PITCH),
+            pitch_upper_limit: Some(std::f32::consts::FRAC_PI_2),
             ..default()
         },
     ));
 }
 
+/// Arrow keys pan the camera, so do WASD unless the letter keys are taken by the order hotkeys
 fn camera_keyboard_controls(
     time: Res<Time>,
     key_input: Res<ButtonInput<KeyCode>>,
+    selected_units: Res<SelectedUnits>,
+    targeting: Res<OrderTargeting>,
+    mut follow: ResMut<CameraFollow>,
     mut pan_orbit_query: Query<(&mut PanOrbitCamera, &mut Transform)>,
 ) {
+    let movement = keyboard_pan_movement(&key_input, !order_hotkeys_active(&selected_units, &targeting));
     for (mut pan_orbit, mut transform) in pan_orbit_query.iter_mut() {
-        let mut  movement = Vec3::ZERO;
-
-            let mut delta_translation = Vec3::ZERO;
-            if key_input.pressed(KeyCode::ArrowRight) || key_input.pressed(KeyCode::KeyD) {
-                movement += Vec3::X;
-            }
-            if key_input.pressed(KeyCode::ArrowLeft) || key_input.pressed(KeyCode::KeyA) {
-                movement += Vec3::NEG_X;
-            }
-            if key_input.pressed(KeyCode::ArrowUp) || key_input.pressed(KeyCode::KeyW) {
-                movement += Vec3::NEG_Z;
-            }
-            if key_input.pressed(KeyCode::ArrowDown) || key_input.pressed(KeyCode::KeyS) {
-                movement += Vec3::Z;
-            }
-
-            if movement != Vec3::ZERO {
-                //Camera should move in X,Z only (horizontal) regardles of the camera rotation
-                let mut rotation = transform.rotation;
-                rotation.x = 0.0;
-                rotation.z = 0.0;
-
-                // The higher camera is, the faster it should move
-                let speed = transform.translation.y * 2.0;
-
-                delta_translation += (rotation * movement).normalize() * time.delta_seconds() * speed;
-
-                transform.translation += delta_translation;
-                pan_orbit.target_focus += delta_translation;
-            }
+        if movement != Vec3::ZERO {
+            follow.enabled = false;
+        }
+        pan_camera(&mut pan_orbit, &mut transform, movement, time.delta_seconds());
 
         // Force camera to update its transform
         pan_orbit.force_update = true;
     }
-}
\ No newline at end of file
+}
+
+/// Direction of the keyboard panning, WASD are only considered when `letter_keys` is set
+pub fn keyboard_pan_movement(key_input: &ButtonInput<KeyCode>, letter_keys: bool) -> Vec3 {
+    let pressed = |arrow: KeyCode, letter: KeyCode| key_input.pressed(arrow) || (letter_keys && key_input.pressed(letter));
+    let mut movement = Vec3::ZERO;
+    if pressed(KeyCode::ArrowRight, KeyCode::KeyD) {
+        movement += Vec3::X;
+    }
+    if pressed(KeyCode::ArrowLeft, KeyCode::KeyA) {
+        movement += Vec3::NEG_X;
+    }
+    if pressed(KeyCode::ArrowUp, KeyCode::KeyW) {
+        movement += Vec3::NEG_Z;
+    }
+    if pressed(KeyCode::ArrowDown, KeyCode::KeyS) {
+        movement += Vec3::Z;
+    }
+    movement
+}
+
+/// Pans the camera while the cursor is near the window border, faster the closer it gets to the border.
+/// Stays still while a selection is being drawn or the window is not focused.
+#[allow(clippy::type_complexity)]
+fn camera_edge_scrolling(
+    time: Res<Time>,
+    edge_scrolling: Res<EdgeScrolling>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    selecting_q: Query<(), Or<(With<SelectionBoxInProcess>, With<SelectionLassoInProcess>)>>,
+    mut follow: ResMut<CameraFollow>,
+    mut pan_orbit_query: Query<(&mut PanOrbitCamera, &mut Transform)>,
+) {
+    let window = q_window.single();
+    if !edge_scrolling.enabled || !window.focused || !selecting_q.is_empty() {
+        return;
+    }
+    let Some(cursor_position) = window.cursor_position() else {
+        return;
+    };
+
+    // 0 at the inner side of the margin, 1 at the border
+    let ramp = |distance_to_border: f32| (1.0 - distance_to_border / edge_scrolling.margin).clamp(0.0, 1.0);
+    let size = Vec2::new(window.width(), window.height());
+    let movement = Vec3::new(
+        ramp(size.x - cursor_position.x) - ramp(cursor_position.x),
+        0.0,
+        ramp(size.y - cursor_position.y) - ramp(cursor_position.y),
+    );
+    if movement == Vec3::ZERO {
+        return;
+    }
+
+    follow.enabled = false;
+    for (mut pan_orbit, mut transform) in pan_orbit_query.iter_mut() {
+        pan_camera(&mut pan_orbit, &mut transform, movement, time.delta_seconds());
+        pan_orbit.force_update = true;
+    }
+}
+
+/// Moves the camera and its focus horizontally.
+/// `movement` is relative to the camera heading, its length (up to 1) is the fraction of the full speed.
+fn pan_camera(pan_orbit: &mut PanOrbitCamera, transform: &mut Transform, movement: Vec3, delta_seconds: f32) {
+    if movement == Vec3::ZERO {
+        return;
+    }
+
+    //Camera should move in X,Z only (horizontal) regardles of the camera rotation
+    let mut rotation = transform.rotation;
+    rotation.x = 0.0;
+    rotation.z = 0.0;
+    let direction = (rotation.normalize() * movement).with_y(0.0);
+
+    // The further the camera is, the faster it should move
+    let speed = pan_orbit.radius.unwrap_or(pan_orbit.target_radius) * PAN_SPEED;
+
+    let delta_translation = direction.normalize_or_zero() * movement.length().min(1.0) * delta_seconds * speed;
+    transform.translation += delta_translation;
+    pan_orbit.target_focus += delta_translation;
+}
+
+/// Ctrl+F1..F4 stores the current view, F1..F4 flies back to it.
+/// The camera smoothing turns the jump into a flight.
+fn camera_bookmarks(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    mut follow: ResMut<CameraFollow>,
+    mut pan_orbit_query: Query<&mut PanOrbitCamera>,
+) {
+    let Some(index) = BOOKMARK_KEYS.iter().position(|key| keyboard_input.just_pressed(*key)) else {
+        return;
+    };
+
+    for mut pan_orbit in pan_orbit_query.iter_mut() {
+        if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+            bookmarks.slots[index] = Some(CameraView::target_of(&pan_orbit));
+            info!("Camera bookmark {} saved", index + 1);
+        } else if let Some(bookmark) = bookmarks.slots[index] {
+            follow.enabled = false;
+            bookmark.set_target(&mut pan_orbit);
+        }
+    }
+}
+
+fn toggle_camera_follow(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut follow: ResMut<CameraFollow>,
+) {
+    if keyboard_input.just_pressed(FOLLOW_KEY) {
+        follow.enabled = !follow.enabled;
+        info!("Camera follow: {}", follow.enabled);
+    }
+}
+
+/// Keeps the camera focus on the centroid of the selected units
+fn follow_selection(
+    mut follow: ResMut<CameraFollow>,
+    selected_q: Query<&Transform, With<Selected>>,
+    mut pan_orbit_query: Query<&mut PanOrbitCamera>,
+) {
+    if !follow.enabled {
+        return;
+    }
+    if selected_q.is_empty() {
+        follow.enabled = false;
+        return;
+    }
+
+    let centroid = selected_q.iter().map(|tr| tr.translation).sum::<Vec3>() / selected_q.iter().len() as f32;
+    for mut pan_orbit in pan_orbit_query.iter_mut() {
+        // The height is taken care of by the terrain constraint
+        let target_focus = centroid.with_y(pan_orbit.target_focus.y);
+        if target_focus != pan_orbit.target_focus {
+            pan_orbit.target_focus = target_focus;
+        }
+    }
+}
+
+fn remember_alerts(
+    mut reader: EventReader<Alert>,
+    mut last_alert: ResMut<LastAlert>,
+) {
+    if let Some(Alert(position)) = reader.read().last() {
+        last_alert.0 = Some(*position);
+    }
+}
+
+fn jump_to_alert(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    last_alert: Res<LastAlert>,
+    mut follow: ResMut<CameraFollow>,
+    mut center_camera_writer: EventWriter<CenterCamera>,
+) {
+    if !keyboard_input.just_pressed(LAST_ALERT_KEY) {
+        return;
+    }
+    if let Some(position) = last_alert.0 {
+        follow.enabled = false;
+        center_camera_writer.send(CenterCamera(position));
+    }
+}
+
+fn center_camera(
+    mut reader: EventReader<CenterCamera>,
+    mut pan_orbit_query: Query<&mut PanOrbitCamera>,
+) {
+    let Some(CenterCamera(point)) = reader.read().last() else {
+        return;
+    };
+    for mut pan_orbit in pan_orbit_query.iter_mut() {
+        pan_orbit.target_focus = *point;
+    }
+}
+
+/// Keeps the focus over the terrain and on its surface, so panning over plateaus doesn't clip into cliffs.
+/// Zoom and pitch are limited by the camera itself.
+pub fn constrain_camera(
+    time: Res<Time>,
+    heightmap: Res<TerrainHeightmap>,
+    mut pan_orbit_query: Query<&mut PanOrbitCamera>,
+) {
+    let extents = heightmap.extents();
+    for mut pan_orbit in pan_orbit_query.iter_mut() {
+        let focus = pan_orbit.target_focus;
+        let clamped = focus.xz().clamp(extents.min, extents.max);
+        let ground = heightmap.height_at(clamped.x, clamped.y);
+        let height = focus.y.lerp(ground, 1.0 - (-FOCUS_HEIGHT_SMOOTHNESS * time.delta_seconds()).exp());
+
+        let target_focus = Vec3::new(clamped.x, height, clamped.y);
+        // Avoid touching the camera when nothing changes, so it doesn't get updated every frame
+        if target_focus.distance_squared(focus) > 1e-8 {
+            pan_orbit.target_focus = target_focus;
+        }
+    }
+}
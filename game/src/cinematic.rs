@@ -0,0 +1,250 @@
+use std::ops::{Add, Mul, Sub};
+
+use bevy::prelude::*;
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{constrain_camera, CameraFollow, CameraView};
+
+/// Time (seconds) between a newly recorded keyframe and the previous one
+const KEYFRAME_INTERVAL: f32 = 3.0;
+const CAMERA_PATH_FILE: &str = "camera_path.json";
+const RECORD_KEY: KeyCode = KeyCode::KeyK;
+const PLAY_KEY: KeyCode = KeyCode::KeyJ;
+const SAVE_KEY: KeyCode = KeyCode::F5;
+const LOAD_KEY: KeyCode = KeyCode::F9;
+
+pub struct MyCinematicPlugin;
+
+impl Plugin for MyCinematicPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraPath>()
+            .init_resource::<CameraPathPlayback>()
+            .add_systems(Update, camera_path_keys)
+            .add_systems(Update, play_camera_path.after(camera_path_keys).after(constrain_camera));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub view: CameraView,
+}
+
+/// Camera flight through keyframes, e.g. for replays and trailers.
+/// Views between the keyframes are interpolated with Catmull-Rom splines.
+#[derive(Resource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// Sorted by time
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+/// Seconds since the playback started, `None` while not playing
+#[derive(Resource, Debug, Default)]
+pub struct CameraPathPlayback(Option<f32>);
+
+impl CameraPath {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Appends a keyframe [`KEYFRAME_INTERVAL`] after the last one
+    pub fn record(&mut self, view: CameraView) {
+        let time = if self.keyframes.is_empty() { 0.0 } else { self.duration() + KEYFRAME_INTERVAL };
+        self.keyframes.push(CameraKeyframe { time, view });
+    }
+
+    /// View at the given time, the whole path is eased in and out so the flight starts and stops gently
+    pub fn sample(&self, time: f32) -> Option<CameraView> {
+        let (first, last) = (self.keyframes.first()?, self.keyframes.last()?);
+        if self.keyframes.len() == 1 || self.duration() <= 0.0 {
+            return Some(first.view);
+        }
+        let time = self.duration() * ease_in_out(time / self.duration());
+        if time >= last.time {
+            return Some(last.view);
+        }
+
+        let next = self.keyframes.iter().position(|keyframe| keyframe.time > time)?.max(1);
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+
+        // The ends are extended by repeating the first and the last keyframe
+        let before = &self.keyframes[next.saturating_sub(2)];
+        let after = &self.keyframes[(next + 1).min(self.keyframes.len() - 1)];
+        let t = ((time - from.time) / (to.time - from.time)).clamp(0.0, 1.0);
+
+        let views = [before.view, from.view, to.view, after.view];
+        Some(CameraView {
+            focus: catmull_rom(views.map(|v| v.focus), t),
+            yaw: catmull_rom(views.map(|v| v.yaw), t),
+            pitch: catmull_rom(views.map(|v| v.pitch), t),
+            radius: catmull_rom(views.map(|v| v.radius), t),
+        })
+    }
+}
+
+/// Point on the uniform Catmull-Rom spline between `points[1]` (t = 0) and `points[2]` (t = 1)
+fn catmull_rom<T>(points: [T; 4], t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let [p0, p1, p2, p3] = points;
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
+
+fn ease_in_out(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Run condition for the systems that would move the camera during the playback
+pub fn camera_path_playing(playback: Res<CameraPathPlayback>) -> bool {
+    playback.0.is_some()
+}
+
+/// K records the current view, J plays or stops the path, F5/F9 save and load it.
+/// Ctrl+K clears the path.
+fn camera_path_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut path: ResMut<CameraPath>,
+    mut playback: ResMut<CameraPathPlayback>,
+    mut pan_orbit_query: Query<&mut PanOrbitCamera>,
+) {
+    if keyboard_input.just_pressed(RECORD_KEY) {
+        if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+            path.keyframes.clear();
+            info!("Camera path cleared");
+        } else if let Ok(pan_orbit) = pan_orbit_query.get_single() {
+            path.record(CameraView::target_of(pan_orbit));
+            info!("Camera keyframe {} recorded", path.keyframes.len());
+        }
+    }
+
+    if keyboard_input.just_pressed(PLAY_KEY) {
+        if playback.0.is_some() {
+            playback.0 = None;
+            for mut pan_orbit in pan_orbit_query.iter_mut() {
+                pan_orbit.enabled = true;
+            }
+        } else if !path.keyframes.is_empty() {
+            playback.0 = Some(0.0);
+        }
+    }
+
+    if keyboard_input.just_pressed(SAVE_KEY) {
+        let result = serde_json::to_string_pretty(&*path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(CAMERA_PATH_FILE, json).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => info!("Camera path saved to {}", CAMERA_PATH_FILE),
+            Err(e) => warn!("Failed to save the camera path: {}", e),
+        }
+    }
+
+    if keyboard_input.just_pressed(LOAD_KEY) {
+        let result = std::fs::read_to_string(CAMERA_PATH_FILE)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str::<CameraPath>(&json).map_err(|e| e.to_string()));
+        match result {
+            Ok(loaded) => {
+                *path = loaded;
+                info!("Camera path loaded from {}", CAMERA_PATH_FILE);
+            }
+            Err(e) => warn!("Failed to load the camera path: {}", e),
+        }
+    }
+}
+
+/// Drives the camera along the path, user input is ignored meanwhile
+fn play_camera_path(
+    time: Res<Time>,
+    path: Res<CameraPath>,
+    mut playback: ResMut<CameraPathPlayback>,
+    mut follow: ResMut<CameraFollow>,
+    mut pan_orbit_query: Query<&mut PanOrbitCamera>,
+) {
+    let Some(elapsed) = playback.0.as_mut() else {
+        return;
+    };
+    *elapsed += time.delta_seconds();
+    let view = path.sample(*elapsed);
+    // The path may also get cleared while playing
+    let finished = *elapsed > path.duration() || view.is_none();
+    follow.enabled = false;
+
+    for mut pan_orbit in pan_orbit_query.iter_mut() {
+        if let Some(view) = view {
+            view.set_target(&mut pan_orbit);
+            // Skip the camera smoothing, the path is smooth already
+            pan_orbit.focus = view.focus;
+            pan_orbit.yaw = Some(view.yaw);
+            pan_orbit.pitch = Some(view.pitch);
+            pan_orbit.radius = Some(view.radius);
+            pan_orbit.force_update = true;
+        }
+        pan_orbit.enabled = finished;
+    }
+
+    if finished {
+        playback.0 = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(x: f32, yaw: f32) -> CameraView {
+        CameraView { focus: Vec3::new(x, 0.0, 0.0), yaw, pitch: 1.0, radius: 20.0 }
+    }
+
+    fn path(views: &[CameraView]) -> CameraPath {
+        let mut path = CameraPath::default();
+        for view in views {
+            path.record(*view);
+        }
+        path
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        let path = path(&[view(0.0, 0.0), view(10.0, 1.0), view(5.0, 3.0), view(20.0, 2.0)]);
+        assert_eq!(path.duration(), 3.0 * KEYFRAME_INTERVAL);
+        assert_eq!(path.sample(0.0), Some(view(0.0, 0.0)));
+        assert_eq!(path.sample(path.duration()), Some(view(20.0, 2.0)));
+        assert_eq!(path.sample(100.0), Some(view(20.0, 2.0)));
+
+        // The middle of the path is not affected by the easing
+        let middle = path.sample(1.5 * KEYFRAME_INTERVAL).unwrap();
+        assert!(middle.focus.x > 5.0 && middle.focus.x < 10.0);
+    }
+
+    #[test]
+    fn catmull_rom_keeps_evenly_spaced_points_on_a_line() {
+        assert_eq!(catmull_rom([0.0, 1.0, 2.0, 3.0], 0.0), 1.0);
+        assert_eq!(catmull_rom([0.0, 1.0, 2.0, 3.0], 0.5), 1.5);
+        assert_eq!(catmull_rom([0.0, 1.0, 2.0, 3.0], 1.0), 2.0);
+        let point = catmull_rom([Vec3::ZERO, Vec3::X, Vec3::X * 2.0, Vec3::X * 3.0], 0.25);
+        assert!(point.abs_diff_eq(Vec3::X * 1.25, 1e-6));
+    }
+
+    #[test]
+    fn empty_and_single_keyframe_paths() {
+        assert_eq!(CameraPath::default().sample(1.0), None);
+        assert_eq!(path(&[view(3.0, 0.5)]).sample(1.0), Some(view(3.0, 0.5)));
+    }
+
+    #[test]
+    fn survives_json_round_trip() {
+        let path = path(&[view(0.0, 0.0), view(10.0, 1.0)]);
+        let json = serde_json::to_string(&path).unwrap();
+        assert_eq!(serde_json::from_str::<CameraPath>(&json).unwrap(), path);
+    }
+}
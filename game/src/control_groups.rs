@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+
+use crate::{camera::CenterCamera, units::{MovableUnit, SelectedUnits}};
+
+/// Second press of the same group key within this time (seconds) centers the camera on the group
+const DOUBLE_TAP_TIME: f32 = 0.3;
+
+const GROUP_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3,
+    KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6,
+    KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+];
+
+pub struct MyControlGroupsPlugin;
+
+impl Plugin for MyControlGroupsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ControlGroups>()
+            .add_systems(Update, prune_control_groups)
+            .add_systems(Update, control_group_keys.after(prune_control_groups));
+    }
+}
+
+/// Units assigned to number keys
+#[derive(Resource, Default, Debug)]
+pub struct ControlGroups {
+    pub groups: [Vec<Entity>; 9],
+    /// Group recalled last and when it happened, to detect double taps
+    last_recall: Option<(usize, f32)>,
+}
+
+fn control_group_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut control_groups: ResMut<ControlGroups>,
+    mut selected_units: ResMut<SelectedUnits>,
+    units_q: Query<&Transform, With<MovableUnit>>,
+    mut center_camera_writer: EventWriter<CenterCamera>,
+) {
+    let Some(index) = GROUP_KEYS.iter().position(|key| keyboard_input.just_pressed(*key)) else {
+        return;
+    };
+
+    if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        control_groups.groups[index] = selected_units.commandable().to_vec();
+    } else if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        let group = &mut control_groups.groups[index];
+        for entity in selected_units.commandable() {
+            if !group.contains(entity) {
+                group.push(*entity);
+            }
+        }
+    } else {
+        let group = &control_groups.groups[index];
+        if group.is_empty() {
+            return;
+        }
+        selected_units.set(group, false);
+
+        let now = time.elapsed_seconds();
+        let double_tap = control_groups.last_recall
+            .is_some_and(|(last_index, last_time)| last_index == index && now - last_time < DOUBLE_TAP_TIME);
+        if double_tap {
+            let positions: Vec<Vec3> = units_q.iter_many(group).map(|tr| tr.translation).collect();
+            if !positions.is_empty() {
+                center_camera_writer.send(CenterCamera(positions.iter().sum::<Vec3>() / positions.len() as f32));
+            }
+        }
+        control_groups.last_recall = Some((index, now));
+    }
+}
+
+/// Forgets units that are gone
+fn prune_control_groups(
+    mut control_groups: ResMut<ControlGroups>,
+    units_q: Query<(), With<MovableUnit>>,
+) {
+    for group in control_groups.groups.iter_mut() {
+        group.retain(|entity| units_q.contains(*entity));
+    }
+}
@@ -61,7 +61,7 @@ struct DebugMaterialIterator<'a> {
 }
 
 impl DebugMaterialIterator<'_> {
-    pub fn new(materials: &mut Assets<StandardMaterial>) -> DebugMaterialIterator {
+    pub fn new(materials: &mut Assets<StandardMaterial>) -> DebugMaterialIterator<'_> {
         DebugMaterialIterator {
             materials, next_color: 0
         }
@@ -0,0 +1,92 @@
+use bevy::{math::bounding::Aabb3d, prelude::*};
+
+use crate::{terrain::TerrainHeightmap, units::LOCAL_PLAYER};
+
+pub struct MyEconomyPlugin;
+
+impl Plugin for MyEconomyPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, setup_resource_nodes)
+            .add_systems(Startup, setup_buildings);
+    }
+}
+
+/// Deposit on the map that units can gather
+#[derive(Component, Debug)]
+pub struct ResourceNode {
+    pub amount: u32,
+    pub half_size: f32,
+}
+
+impl ResourceNode {
+    pub fn bounds(&self, translation: Vec3) -> Aabb3d {
+        Aabb3d::new(translation, Vec3::splat(self.half_size))
+    }
+}
+
+/// Static structure owned by a player
+#[derive(Component, Debug)]
+pub struct Building {
+    pub half_size: f32,
+}
+
+impl Building {
+    pub fn bounds(&self, translation: Vec3) -> Aabb3d {
+        Aabb3d::new(translation, Vec3::splat(self.half_size))
+    }
+}
+
+fn setup_resource_nodes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    heightmap: Res<TerrainHeightmap>,
+) {
+    let half_size = 1.0;
+    let mesh = meshes.add(Cuboid { half_size: Vec3::splat(half_size) });
+    let material = materials.add(StandardMaterial {
+        base_color: Color::srgb(0.9, 0.75, 0.1),
+        ..default()
+    });
+
+    for (x, z) in [(-15.0, 12.0), (-11.0, 15.0), (18.0, 14.0)] {
+        let position = Vec3::new(x, heightmap.height_at(x, z) + half_size, z);
+        commands.spawn(
+            (
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(position),
+                    ..default()
+                },
+                ResourceNode { amount: 1000, half_size },
+            )
+        );
+    }
+}
+
+fn setup_buildings(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    heightmap: Res<TerrainHeightmap>,
+) {
+    let half_size = 2.5;
+    let (x, z) = (-14.0, 3.0);
+    commands.spawn(
+        (
+            PbrBundle {
+                mesh: meshes.add(Cuboid { half_size: Vec3::splat(half_size) }),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::srgb(0.35, 0.4, 0.5),
+                    ..default()
+                }),
+                transform: Transform::from_xyz(x, heightmap.height_at(x, z) + half_size, z),
+                ..default()
+            },
+            Building { half_size },
+            LOCAL_PLAYER,
+        )
+    );
+}
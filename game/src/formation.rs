@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+
+use crate::units::MovableUnit;
+
+/// How far (in world units) a member may lag behind its slot before the group starts slowing down.
+const LAG_TOLERANCE: f32 = 1.0;
+/// Lag at which the group stops completely and waits for the member to catch up.
+const REGROUP_DISTANCE: f32 = 6.0;
+
+pub struct MyFormationPlugin;
+
+impl Plugin for MyFormationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<GroupMovementMode>()
+            .add_systems(Update, toggle_group_movement)
+            .add_systems(Update, move_groups);
+    }
+}
+
+/// When enabled, a move order given to several units makes them travel as one group
+/// at the speed of the slowest member instead of each unit going at its own pace.
+#[derive(Resource, Default, Debug)]
+pub struct GroupMovementMode {
+    pub enabled: bool,
+}
+
+/// Virtual anchor of a group of units moving in formation.
+#[derive(Component, Debug)]
+pub struct MovingGroup {
+    pub members: Vec<Entity>,
+    /// Current position of the formation center
+    pub center: Vec3,
+    pub destination: Vec3,
+    /// Normalized direction of travel on the XZ plane
+    pub heading: Vec3,
+    /// Speed of the slowest member
+    pub speed: f32,
+}
+
+/// Marks a unit as a member of a [`MovingGroup`].
+#[derive(Component, Debug)]
+pub struct GroupMember {
+    pub group: Entity,
+    /// Offset from the formation center: `x` - to the right of the heading, `y` - backwards
+    pub slot: Vec2,
+}
+
+impl MovingGroup {
+    pub fn slot_position(&self, slot: Vec2) -> Vec3 {
+        let right = Vec3::new(-self.heading.z, 0.0, self.heading.x);
+        self.center + right * slot.x - self.heading * slot.y
+    }
+}
+
+/// Spawns a group that moves the given units to the destination in formation.
+///
+/// ## Args:
+/// * `units` - (entity, current position, unit) for every member
+/// * `destination` - point where the formation center should arrive
+pub fn form_group(commands: &mut Commands, units: &[(Entity, Vec3, &MovableUnit)], destination: Vec3) {
+    if units.is_empty() {
+        return;
+    }
+
+    let center = units.iter().map(|(_, pos, _)| *pos).sum::<Vec3>() / units.len() as f32;
+    let heading = (destination - center).with_y(0.0).try_normalize().unwrap_or(Vec3::NEG_Z);
+    let right = Vec3::new(-heading.z, 0.0, heading.x);
+
+    let speed = units.iter().map(|(_, _, u)| u.speed).fold(f32::INFINITY, f32::min);
+    let spacing = units.iter().map(|(_, _, u)| u.half_size).fold(0.0, f32::max) * 2.0;
+
+    // Units that are already in front take the front rows, so nobody has to drive through the group
+    let mut ordered: Vec<(Entity, f32, f32)> = units.iter()
+        .map(|(e, pos, _)| (*e, (*pos - center).dot(heading), (*pos - center).dot(right)))
+        .collect();
+    ordered.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let columns = (units.len() as f32).sqrt().ceil() as usize;
+    let rows = units.len().div_ceil(columns);
+
+    let group = commands.spawn_empty().id();
+    let mut members = Vec::with_capacity(units.len());
+    for (row_ind, row) in ordered.chunks_mut(columns).enumerate() {
+        row.sort_by(|a, b| a.2.total_cmp(&b.2));
+        for (col_ind, (entity, _, _)) in row.iter().enumerate() {
+            let slot = Vec2::new(
+                (col_ind as f32 - (row.len() - 1) as f32 / 2.0) * spacing,
+                (row_ind as f32 - (rows - 1) as f32 / 2.0) * spacing,
+            );
+            commands.entity(*entity).insert(GroupMember { group, slot });
+            members.push(*entity);
+        }
+    }
+
+    commands.entity(group).insert(MovingGroup { members, center, destination, heading, speed });
+}
+
+fn toggle_group_movement(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut group_mode: ResMut<GroupMovementMode>,
+) {
+    if keyboard_input.just_pressed(KeyCode::KeyG) {
+        group_mode.enabled = !group_mode.enabled;
+        info!("Group movement: {}", group_mode.enabled);
+    }
+}
+
+fn move_groups(
+    mut commands: Commands,
+    mut groups_q: Query<(Entity, &mut MovingGroup)>,
+    mut members_q: Query<(&Transform, &mut MovableUnit, &GroupMember)>,
+    time: Res<Time>,
+) {
+    for (group_entity, mut group) in groups_q.iter_mut() {
+        // Units that got another order (or were destroyed) leave the group
+        group.members.retain(|e| members_q.get(*e).is_ok_and(|(_, _, m)| m.group == group_entity));
+        if group.members.is_empty() {
+            commands.entity(group_entity).despawn();
+            continue;
+        }
+
+        // The formation slows down and eventually waits when someone falls behind,
+        // e.g. after squeezing through a chokepoint one by one
+        let mut max_lag: f32 = 0.0;
+        for (tr, _, member) in members_q.iter_many(&group.members) {
+            let slot_pos = group.slot_position(member.slot);
+            max_lag = max_lag.max(tr.translation.xz().distance(slot_pos.xz()));
+        }
+        let pace = 1.0 - ((max_lag - LAG_TOLERANCE) / (REGROUP_DISTANCE - LAG_TOLERANCE)).clamp(0.0, 1.0);
+
+        let destination = group.destination;
+        group.center = group.center.move_towards(destination, group.speed * pace * time.delta_seconds());
+        let arrived = group.center.distance(destination) < 0.1;
+
+        let mut iter = members_q.iter_many_mut(&group.members);
+        while let Some((_, mut unit, member)) = iter.fetch_next() {
+            unit.destination = Some(group.slot_position(member.slot));
+        }
+
+        if arrived {
+            for member in group.members.iter() {
+                commands.entity(*member).remove::<GroupMember>();
+            }
+            commands.entity(group_entity).despawn();
+        }
+    }
+}
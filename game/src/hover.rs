@@ -0,0 +1,138 @@
+use bevy::{math::bounding::BoundingVolume, prelude::*, window::PrimaryWindow};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{orders::OrderQueue, unit_defs::UnitDef, units::{Health, MovableUnit, Owner, UnitType, LOCAL_PLAYER}, util::picking::pick_entity};
+
+/// How long (seconds) the cursor has to rest on a unit before its tooltip shows up
+const TOOLTIP_DELAY: f32 = 0.5;
+/// Tooltip offset from the cursor (pixels), so it doesn't cover the unit itself
+const TOOLTIP_OFFSET: Vec2 = Vec2::new(16.0, 16.0);
+
+pub struct MyHoverPlugin;
+
+impl Plugin for MyHoverPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<HoveredUnit>()
+            .add_systems(Update, detect_hovered_unit)
+            .add_systems(Update, highlight_hovered_unit.after(detect_hovered_unit))
+            .add_systems(Update, show_unit_tooltip.after(detect_hovered_unit));
+    }
+}
+
+/// Unit under the cursor
+#[derive(Resource, Default, Debug)]
+pub struct HoveredUnit {
+    pub entity: Option<Entity>,
+    /// Time (seconds since startup) when the cursor got onto the unit
+    pub since: f32,
+}
+
+#[derive(Component)]
+struct UnitTooltip;
+
+fn detect_hovered_unit(
+    mut hovered: ResMut<HoveredUnit>,
+    time: Res<Time>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    units_q: Query<(Entity, &Transform, &MovableUnit)>,
+) {
+    let (camera, camera_transform) = q_camera.single();
+    let entity = q_window.single().cursor_position()
+        .and_then(|cursor_position| camera.viewport_to_world(camera_transform, cursor_position))
+        .and_then(|ray| pick_entity(ray, units_q.iter().map(|(e, tr, unit)| (e, unit.bounds(tr.translation)))));
+
+    if hovered.entity != entity {
+        hovered.entity = entity;
+        hovered.since = time.elapsed_seconds();
+    }
+}
+
+/// Outlines the hovered unit with its owner's color
+fn highlight_hovered_unit(
+    mut gizmos: Gizmos,
+    hovered: Res<HoveredUnit>,
+    units_q: Query<(&Transform, &MovableUnit, &Owner)>,
+) {
+    let Some((tr, unit, owner)) = hovered.entity.and_then(|entity| units_q.get(entity).ok()) else {
+        return;
+    };
+
+    let bounds = unit.bounds(tr.translation);
+    let color = if *owner == LOCAL_PLAYER { Color::srgb(0.2, 1.0, 0.3) } else { Color::srgb(1.0, 0.25, 0.2) };
+    let outline = Transform::from_translation(bounds.center().into()).with_scale((bounds.half_size() * 2.0).into());
+    gizmos.cuboid(outline, color);
+}
+
+/// Shows the name, owner, health, weapons and cost of the hovered unit next to the cursor,
+/// and the current order if the unit is an own one
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn show_unit_tooltip(
+    mut commands: Commands,
+    hovered: Res<HoveredUnit>,
+    time: Res<Time>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    units_q: Query<(&UnitType, &Owner, &Health, &OrderQueue, Option<&Handle<UnitDef>>)>,
+    unit_defs: Res<Assets<UnitDef>>,
+    mut tooltip_q: Query<(Entity, &mut Style, &Children), With<UnitTooltip>>,
+    mut text_q: Query<&mut Text>,
+) {
+    let cursor_position = q_window.single().cursor_position();
+    let info = hovered.entity
+        .filter(|_| time.elapsed_seconds() - hovered.since >= TOOLTIP_DELAY)
+        .and_then(|entity| units_q.get(entity).ok());
+
+    let (Some(cursor_position), Some((unit_type, owner, health, queue, def))) = (cursor_position, info) else {
+        for (entity, _, _) in tooltip_q.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    };
+
+    let owner_name = if *owner == LOCAL_PLAYER { "You".to_string() } else { format!("Player {}", owner.0) };
+    let mut content = format!("{}\nOwner: {}\nHealth: {:.0}/{:.0}", unit_type.0, owner_name, health.current, health.max);
+    // Orders of other players are their secret
+    if *owner == LOCAL_PLAYER {
+        content += "\n";
+        content += queue.current().map_or("Idle", |order| order.name());
+    }
+    if let Some(def) = def.and_then(|def| unit_defs.get(def)) {
+        for weapon in def.weapons.iter() {
+            content += &format!(
+                "\n{}: {:.0} dmg, {:.0} range, {:.1}s reload",
+                weapon.name, weapon.damage, weapon.range, weapon.reload_time,
+            );
+        }
+        content += &format!("\nCost: {}", def.cost);
+    }
+    let position = cursor_position + TOOLTIP_OFFSET;
+
+    if let Ok((_, mut style, children)) = tooltip_q.get_single_mut() {
+        style.left = Val::Px(position.x);
+        style.top = Val::Px(position.y);
+        if let Some(mut text) = children.first().and_then(|child| text_q.get_mut(*child).ok()) {
+            text.sections[0].value = content;
+        }
+        return;
+    }
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(position.x),
+                    top: Val::Px(position.y),
+                    padding: UiRect::all(Val::Px(6.0)),
+                    ..Default::default()
+                },
+                background_color: BackgroundColor(Color::srgba(0.05, 0.05, 0.08, 0.85)),
+                ..Default::default()
+            },
+            UnitTooltip,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(content, TextStyle { font_size: 16.0, ..default() }));
+        });
+}
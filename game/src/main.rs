@@ -3,16 +3,34 @@ mod debug;
 mod camera;
 mod selection;
 mod units;
+mod unit_defs;
 mod terrain;
+mod formation;
+mod orders;
+mod economy;
+mod control_groups;
+mod hover;
+mod cinematic;
+mod spatial;
+mod strategic_view;
 
 use bevy::prelude::*;
 use bevy::pbr::CascadeShadowConfigBuilder;
 use bevy_framepace::{FramepaceSettings, Limiter};
 
 use camera::MyCameraPlugin;
+use cinematic::MyCinematicPlugin;
+use control_groups::MyControlGroupsPlugin;
 use debug::MyDebugSpatialPlugin;
+use economy::MyEconomyPlugin;
+use formation::MyFormationPlugin;
+use hover::MyHoverPlugin;
+use orders::MyOrdersPlugin;
 use selection::{MySelectionPlugin, SelectionBoxCompleted};
+use spatial::MySpatialPlugin;
+use strategic_view::MyStrategicViewPlugin;
 use terrain::MyTerrainPlugin;
+use unit_defs::MyUnitDefsPlugin;
 use units::MyUnitsPlugin;
 
 fn main() {
@@ -28,8 +46,17 @@ fn main() {
         .add_plugins(bevy_framepace::FramepacePlugin)
         .add_plugins(MyTerrainPlugin)
         .add_plugins(MyCameraPlugin)
+        .add_plugins(MyCinematicPlugin)
         .add_plugins(MySelectionPlugin)
+        .add_plugins(MyControlGroupsPlugin)
+        .add_plugins(MyHoverPlugin)
+        .add_plugins(MyUnitDefsPlugin)
         .add_plugins(MyUnitsPlugin)
+        .add_plugins(MySpatialPlugin)
+        .add_plugins(MyStrategicViewPlugin)
+        .add_plugins(MyFormationPlugin)
+        .add_plugins(MyOrdersPlugin)
+        .add_plugins(MyEconomyPlugin)
         .add_plugins(MyDebugSpatialPlugin)
         .add_systems(Startup, init_configs_system)
         .add_systems(Startup, setup_scene)
@@ -0,0 +1,660 @@
+use std::collections::VecDeque;
+
+use bevy::{ecs::entity::EntityHashMap, input::InputSystem, prelude::*, window::PrimaryWindow};
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::Alert, economy::ResourceNode, formation::{form_group, GroupMember}, spatial::SpatialIndex, terrain::TerrainHeightmap, unit_defs::UnitDef, units::{Health, MovableUnit, Owner, Selected, SelectedUnits, LOCAL_PLAYER}, util::{picking::pick_entity, projection::project_on_terrain}};
+
+/// Route markers are lifted a bit so they are not hidden by the terrain
+const ROUTE_ELEVATION: f32 = 0.2;
+/// Distance at which units on attack-move, patrol or hold position spot enemies and engage them
+const ENGAGE_RANGE: f32 = 15.0;
+/// Gap a following unit keeps between itself and the target
+const FOLLOW_DISTANCE: f32 = 3.0;
+/// Gap between a gathering unit and the resource node
+const GATHER_DISTANCE: f32 = 1.0;
+/// Damage dealt by units without a definition or without weapons reaching the enemy
+const DEFAULT_DAMAGE_PER_SECOND: f32 = 10.0;
+
+pub struct MyOrdersPlugin;
+
+impl Plugin for MyOrdersPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<UnitOrder>()
+            .init_resource::<OrderTargeting>()
+            // Runs before Update so that the click choosing the target doesn't also start a selection box
+            .add_systems(PreUpdate, order_targeting.after(InputSystem))
+            .add_systems(Update, order_hotkeys)
+            .add_systems(Update, apply_unit_orders)
+            .add_systems(Update, execute_orders.after(apply_unit_orders))
+            .add_systems(Update, fire_at_engaged_enemies.after(execute_orders))
+            .add_systems(Update, remove_destroyed_units.after(fire_at_engaged_enemies))
+            .add_systems(Update, draw_order_routes);
+    }
+}
+
+/// Order given to a set of units.
+/// Player input, AI players, replays, scripts and network all drive units by sending these events,
+/// so every order goes through the same path.
+///
+/// Units and targets are referred to by their [`Entity`] ids, which are only meaningful within the world
+/// that issued them: a serialized order can be replayed into the same running game, not loaded into another one.
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnitOrder {
+    pub units: Vec<Entity>,
+    pub kind: OrderKind,
+    pub target: OrderTarget,
+    /// Append to the units' order queues instead of replacing them
+    pub queued: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderKind {
+    Stop,
+    Move,
+    /// Move keeping the units together in a formation
+    GroupMove,
+    AttackMove,
+    Patrol,
+    HoldPosition,
+    Follow,
+    Attack,
+    Gather,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum OrderTarget {
+    #[default]
+    None,
+    Point(Vec3),
+    /// Unit or any other object on the map, e.g. a resource node
+    Unit(Entity),
+}
+
+impl UnitOrder {
+    /// Order to put into the units' queues, `None` if the target doesn't suit the kind of the order
+    pub fn order(&self) -> Option<Order> {
+        match (self.kind, self.target) {
+            (OrderKind::Stop, _) => Some(Order::Stop),
+            (OrderKind::HoldPosition, _) => Some(Order::HoldPosition),
+            (OrderKind::Move | OrderKind::GroupMove, OrderTarget::Point(point)) => Some(Order::Move(point)),
+            (OrderKind::AttackMove, OrderTarget::Point(point)) => Some(Order::AttackMove(point)),
+            (OrderKind::Patrol, OrderTarget::Point(point)) => Some(Order::Patrol(point)),
+            (OrderKind::Follow, OrderTarget::Unit(target)) => Some(Order::Follow(target)),
+            (OrderKind::Attack, OrderTarget::Unit(target)) => Some(Order::Attack(target)),
+            (OrderKind::Gather, OrderTarget::Unit(node)) => Some(Order::Gather(node)),
+            _ => None,
+        }
+    }
+}
+
+/// Order as it's kept in the unit's queue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    /// Drop whatever the unit is doing
+    Stop,
+    Move(Vec3),
+    /// Move, but stop to fight enemies met on the way
+    AttackMove(Vec3),
+    /// Go back and forth between the point where the order started and the target, fighting enemies
+    Patrol(Vec3),
+    /// Stay in place, fighting enemies in range without chasing them
+    HoldPosition,
+    /// Keep close to another unit
+    Follow(Entity),
+    /// Chase the enemy unit and fight it
+    Attack(Entity),
+    /// Go to the resource node and stay there while it lasts
+    Gather(Entity),
+}
+
+impl Order {
+    /// Point on the map where the order leads the unit to
+    pub fn target_point(&self) -> Option<Vec3> {
+        match self {
+            Order::Move(point) | Order::AttackMove(point) | Order::Patrol(point) => Some(*point),
+            _ => None,
+        }
+    }
+
+    /// Short description for the UI
+    pub fn name(&self) -> &'static str {
+        match self {
+            Order::Stop => "Stopping",
+            Order::Move(_) => "Moving",
+            Order::AttackMove(_) => "Attack-moving",
+            Order::Patrol(_) => "Patrolling",
+            Order::HoldPosition => "Holding position",
+            Order::Follow(_) => "Following",
+            Order::Attack(_) => "Attacking",
+            Order::Gather(_) => "Gathering",
+        }
+    }
+
+    fn engages_enemies(&self) -> bool {
+        matches!(self, Order::AttackMove(_) | Order::Patrol(_) | Order::HoldPosition)
+    }
+}
+
+/// Progress of the order being executed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OrderState {
+    /// The order hasn't been handed over to the unit yet
+    #[default]
+    Pending,
+    Active,
+    /// Patrol heading from one point to another
+    Patrolling { from: Vec3, to: Vec3 },
+}
+
+/// Orders of a unit. The first one is being executed, the rest wait for their turn.
+#[derive(Component, Default, Debug)]
+pub struct OrderQueue {
+    pub orders: VecDeque<Order>,
+    pub state: OrderState,
+    /// Enemy the unit has stopped to fight with, the order continues once it's gone
+    pub engaged: Option<Entity>,
+}
+
+impl OrderQueue {
+    pub fn current(&self) -> Option<&Order> {
+        self.orders.front()
+    }
+
+    /// Drops all orders and starts the new one
+    pub fn replace(&mut self, order: Order) {
+        self.orders.clear();
+        self.orders.push_back(order);
+        self.state = OrderState::Pending;
+        self.engaged = None;
+    }
+
+    /// Adds the order to the end of the queue
+    pub fn push(&mut self, order: Order) {
+        self.orders.push_back(order);
+    }
+
+    fn complete_current(&mut self) {
+        self.orders.pop_front();
+        self.state = OrderState::Pending;
+        self.engaged = None;
+    }
+}
+
+/// Order waiting for the player to click its target
+#[derive(Resource, Default, Debug)]
+pub struct OrderTargeting(pub Option<TargetedOrder>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetedOrder {
+    AttackMove,
+    Patrol,
+    Follow,
+}
+
+pub fn is_queue_modifier_pressed(keyboard_input: &ButtonInput<KeyCode>) -> bool {
+    keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+}
+
+/// Gives the order to a unit, either replacing its current orders or queueing after them.
+fn issue_order(commands: &mut Commands, entity: Entity, queue: &mut OrderQueue, order: Order, queued: bool) {
+    if queued {
+        queue.push(order);
+    } else {
+        queue.replace(order);
+        commands.entity(entity).remove::<GroupMember>();
+    }
+}
+
+/// Letter keys give orders while there are units to command or an order waits for its target,
+/// otherwise WASD pan the camera
+pub fn order_hotkeys_active(selected_units: &SelectedUnits, targeting: &OrderTargeting) -> bool {
+    targeting.0.is_some() || !selected_units.commandable().is_empty()
+}
+
+fn order_hotkeys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    selected_units: Res<SelectedUnits>,
+    mut targeting: ResMut<OrderTargeting>,
+    mut orders_writer: EventWriter<UnitOrder>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        targeting.0 = None;
+    }
+    if !order_hotkeys_active(&selected_units, &targeting) {
+        return;
+    }
+
+    let kind = if keyboard_input.just_pressed(KeyCode::KeyS) {
+        OrderKind::Stop
+    } else if keyboard_input.just_pressed(KeyCode::KeyH) {
+        OrderKind::HoldPosition
+    } else {
+        if keyboard_input.just_pressed(KeyCode::KeyA) {
+            targeting.0 = Some(TargetedOrder::AttackMove);
+        } else if keyboard_input.just_pressed(KeyCode::KeyP) {
+            targeting.0 = Some(TargetedOrder::Patrol);
+        } else if keyboard_input.just_pressed(KeyCode::KeyF) {
+            targeting.0 = Some(TargetedOrder::Follow);
+        }
+        return;
+    };
+
+    orders_writer.send(UnitOrder {
+        units: selected_units.commandable().to_vec(),
+        kind,
+        target: OrderTarget::None,
+        queued: is_queue_modifier_pressed(&keyboard_input),
+    });
+}
+
+/// Completes an order waiting for a target: left click chooses the target, right click cancels the order.
+#[allow(clippy::too_many_arguments)]
+fn order_targeting(
+    mut targeting: ResMut<OrderTargeting>,
+    mut mouse_input: ResMut<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    selected_units: Res<SelectedUnits>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    heightmap: Res<TerrainHeightmap>,
+    units_q: Query<(Entity, &Transform, &MovableUnit)>,
+    mut orders_writer: EventWriter<UnitOrder>,
+) {
+    let Some(targeted_order) = targeting.0 else {
+        return;
+    };
+    if mouse_input.clear_just_pressed(MouseButton::Right) {
+        targeting.0 = None;
+        return;
+    }
+    if !mouse_input.clear_just_pressed(MouseButton::Left) {
+        return;
+    }
+    targeting.0 = None;
+
+    let (camera, camera_transform) = q_camera.single();
+    let window = q_window.single();
+    let Some(cursor_position) = window.cursor_position() else {
+        return;
+    };
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
+        return;
+    };
+    let Some(global_cursor) = project_on_terrain(cursor_position, camera, camera_transform, &heightmap) else {
+        return;
+    };
+
+    let (kind, target) = match targeted_order {
+        TargetedOrder::AttackMove => (OrderKind::AttackMove, OrderTarget::Point(global_cursor)),
+        TargetedOrder::Patrol => (OrderKind::Patrol, OrderTarget::Point(global_cursor)),
+        TargetedOrder::Follow => {
+            let units = units_q.iter().map(|(e, tr, unit)| (e, unit.bounds(tr.translation)));
+            let Some(target) = pick_entity(ray, units) else {
+                return;
+            };
+            (OrderKind::Follow, OrderTarget::Unit(target))
+        }
+    };
+
+    orders_writer.send(UnitOrder {
+        units: selected_units.commandable().to_vec(),
+        kind,
+        target,
+        queued: is_queue_modifier_pressed(&keyboard_input),
+    });
+}
+
+/// The only place where orders get into the units' queues
+fn apply_unit_orders(
+    mut commands: Commands,
+    mut reader: EventReader<UnitOrder>,
+    mut units_q: Query<(Entity, &Transform, &MovableUnit, &mut OrderQueue)>,
+) {
+    for unit_order in reader.read() {
+        let Some(order) = unit_order.order() else {
+            warn!("Ignoring order with unsuitable target: {:?}", unit_order);
+            continue;
+        };
+
+        for entity in unit_order.units.iter() {
+            // A unit can't follow itself
+            if order == Order::Follow(*entity) {
+                continue;
+            }
+            if let Ok((_, _, _, mut queue)) = units_q.get_mut(*entity) {
+                issue_order(&mut commands, *entity, &mut queue, order, unit_order.queued);
+            }
+        }
+
+        // Formations are kept only for immediate orders, queued waypoints are driven by each unit on its own
+        if let (OrderKind::GroupMove, OrderTarget::Point(destination), false) = (unit_order.kind, unit_order.target, unit_order.queued) {
+            if unit_order.units.len() > 1 {
+                let members: Vec<_> = units_q.iter_many(&unit_order.units)
+                    .map(|(entity, tr, unit, _)| (entity, tr.translation, unit))
+                    .collect();
+                form_group(&mut commands, &members, destination);
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn execute_orders(
+    mut units_q: Query<(Entity, &Transform, &Owner, &mut OrderQueue, &mut MovableUnit, Option<&GroupMember>)>,
+    nodes_q: Query<(&Transform, &ResourceNode)>,
+    spatial_index: Res<SpatialIndex>,
+    mut alert_writer: EventWriter<Alert>,
+) {
+    let units: EntityHashMap<(Vec3, Owner, f32)> = units_q.iter()
+        .map(|(entity, tr, owner, _, unit, _)| (entity, (tr.translation, *owner, unit.half_size)))
+        .collect();
+
+    for (entity, tr, owner, mut queue, mut unit, group_member) in units_q.iter_mut() {
+        unit.face_towards = None;
+
+        // While in a formation the group drives the unit to its slot and decides when it has arrived
+        if group_member.is_some() {
+            queue.state = OrderState::Active;
+            continue;
+        }
+
+        let Some(order) = queue.current().copied() else {
+            continue;
+        };
+        let position = tr.translation;
+
+        if order.engages_enemies() {
+            let is_enemy = |other: Entity| units.get(&other).is_some_and(|(_, other_owner, _)| *other_owner != *owner);
+            let enemy = spatial_index.k_nearest(position.xz(), 1, ENGAGE_RANGE, is_enemy).first()
+                .and_then(|enemy| units.get(enemy).map(|(enemy_pos, _, _)| (*enemy, *enemy_pos)));
+
+            if let Some((enemy_entity, enemy_pos)) = enemy {
+                // Stop and aim at the enemy until it's destroyed or gone out of range
+                if queue.engaged.is_none() && *owner == LOCAL_PLAYER {
+                    alert_writer.send(Alert(position));
+                }
+                queue.engaged = Some(enemy_entity);
+                unit.destination = None;
+                unit.face_towards = Some(enemy_pos);
+                continue;
+            } else if queue.engaged.take().is_some() {
+                unit.destination = match queue.state {
+                    OrderState::Patrolling { to, .. } => Some(to),
+                    _ => order.target_point(),
+                };
+            }
+        }
+
+        match (order, queue.state) {
+            (Order::Stop, _) => {
+                unit.destination = None;
+                queue.complete_current();
+            }
+            (Order::Move(point) | Order::AttackMove(point), OrderState::Pending) => {
+                unit.destination = Some(point);
+                queue.state = OrderState::Active;
+            }
+            (Order::Move(_) | Order::AttackMove(_), _) => {
+                if unit.destination.is_none() {
+                    queue.complete_current();
+                }
+            }
+            (Order::Patrol(point), OrderState::Pending) => {
+                unit.destination = Some(point);
+                queue.state = OrderState::Patrolling { from: position, to: point };
+            }
+            (Order::Patrol(_), OrderState::Patrolling { from, to }) => {
+                if unit.destination.is_none() {
+                    unit.destination = Some(from);
+                    queue.state = OrderState::Patrolling { from: to, to: from };
+                }
+            }
+            (Order::Patrol(point), OrderState::Active) => {
+                queue.state = OrderState::Patrolling { from: position, to: point };
+            }
+            (Order::HoldPosition, _) => {
+                unit.destination = None;
+                queue.state = OrderState::Active;
+            }
+            (Order::Follow(target), _) => {
+                let target = units.get(&target).filter(|_| target != entity);
+                if let Some((target_pos, _, target_half_size)) = target {
+                    let keep_distance = unit.half_size + target_half_size + FOLLOW_DISTANCE;
+                    unit.destination = if position.xz().distance(target_pos.xz()) > keep_distance {
+                        Some(*target_pos)
+                    } else {
+                        None
+                    };
+                    queue.state = OrderState::Active;
+                } else {
+                    unit.destination = None;
+                    queue.complete_current();
+                }
+            }
+            (Order::Attack(target), _) => {
+                if let Some((target_pos, _, _)) = units.get(&target) {
+                    if position.distance(*target_pos) <= ENGAGE_RANGE {
+                        queue.engaged = Some(target);
+                        unit.destination = None;
+                        unit.face_towards = Some(*target_pos);
+                    } else {
+                        unit.destination = Some(*target_pos);
+                    }
+                    queue.state = OrderState::Active;
+                } else {
+                    unit.destination = None;
+                    queue.complete_current();
+                }
+            }
+            (Order::Gather(node), _) => {
+                match nodes_q.get(node) {
+                    Ok((node_tr, resource)) if resource.amount > 0 => {
+                        let gather_distance = unit.half_size + resource.half_size + GATHER_DISTANCE;
+                        unit.destination = if position.xz().distance(node_tr.translation.xz()) > gather_distance {
+                            Some(node_tr.translation)
+                        } else {
+                            None
+                        };
+                        queue.state = OrderState::Active;
+                    }
+                    _ => {
+                        unit.destination = None;
+                        queue.complete_current();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Engaged units wear down their enemy's health with every weapon that reaches it
+fn fire_at_engaged_enemies(
+    time: Res<Time>,
+    unit_defs: Res<Assets<UnitDef>>,
+    units_q: Query<(&Transform, &OrderQueue, Option<&Handle<UnitDef>>)>,
+    mut targets_q: Query<(&Transform, &mut Health)>,
+) {
+    let mut hits = Vec::new();
+    for (tr, queue, def_handle) in units_q.iter() {
+        let Some(enemy) = queue.engaged else {
+            continue;
+        };
+        let Ok((enemy_tr, _)) = targets_q.get(enemy) else {
+            continue;
+        };
+        let distance = tr.translation.distance(enemy_tr.translation);
+        let damage_per_second = def_handle.and_then(|handle| unit_defs.get(handle))
+            .map(|def| def.weapons.iter()
+                .filter(|weapon| weapon.range >= distance && weapon.reload_time > 0.0)
+                .map(|weapon| weapon.damage / weapon.reload_time)
+                .sum::<f32>())
+            .filter(|dps| *dps > 0.0)
+            .unwrap_or(DEFAULT_DAMAGE_PER_SECOND);
+        hits.push((enemy, damage_per_second * time.delta_seconds()));
+    }
+
+    for (enemy, damage) in hits {
+        if let Ok((_, mut health)) = targets_q.get_mut(enemy) {
+            health.current -= damage;
+        }
+    }
+}
+
+/// Units whose health ran out leave the battlefield, the units engaged with them go on with their orders
+fn remove_destroyed_units(
+    mut commands: Commands,
+    units_q: Query<(Entity, &Health), Changed<Health>>,
+) {
+    for (entity, health) in units_q.iter() {
+        if health.current <= 0.0 {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// While shift is held, shows the queued route of every selected unit.
+fn draw_order_routes(
+    mut gizmos: Gizmos,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    units_q: Query<(&Transform, &OrderQueue, &Owner), With<Selected>>,
+    targets_q: Query<&Transform>,
+    heightmap: Res<TerrainHeightmap>,
+) {
+    if !is_queue_modifier_pressed(&keyboard_input) {
+        return;
+    }
+
+    let on_ground = |point: Vec3| point.with_y(heightmap.height_at(point.x, point.z) + ROUTE_ELEVATION);
+
+    // Routes of inspected enemies stay hidden
+    for (tr, queue, _) in units_q.iter().filter(|(_, _, owner)| **owner == LOCAL_PLAYER) {
+        let mut from = on_ground(tr.translation);
+        for order in queue.orders.iter() {
+            let to = match order {
+                Order::Follow(target) | Order::Attack(target) | Order::Gather(target) => targets_q.get(*target).ok().map(|t| t.translation),
+                _ => order.target_point(),
+            };
+            let Some(to) = to.map(on_ground) else {
+                continue;
+            };
+            gizmos.line(from, to, Color::srgb(0.2, 0.9, 0.2));
+            gizmos.circle(to, Dir3::Y, 0.5, Color::srgb(0.2, 0.9, 0.2));
+            from = to;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::keyboard_pan_movement;
+
+    fn press_stop_key(selected: bool) -> (Vec<UnitOrder>, Vec3) {
+        let mut app = App::new();
+        app
+            .add_event::<UnitOrder>()
+            .init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<SelectedUnits>()
+            .init_resource::<OrderTargeting>()
+            .add_systems(Update, order_hotkeys);
+        if selected {
+            let unit = app.world_mut().spawn_empty().id();
+            app.world_mut().resource_mut::<SelectedUnits>().set(&[unit], false);
+        }
+        app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyS);
+        app.update();
+
+        let world = app.world();
+        let orders = world.resource::<Events<UnitOrder>>().iter_current_update_events().cloned().collect();
+        let letter_keys = !order_hotkeys_active(world.resource::<SelectedUnits>(), world.resource::<OrderTargeting>());
+        (orders, keyboard_pan_movement(world.resource::<ButtonInput<KeyCode>>(), letter_keys))
+    }
+
+    #[test]
+    fn stop_key_pans_without_selection() {
+        let (orders, movement) = press_stop_key(false);
+        assert!(orders.is_empty());
+        assert_eq!(movement, Vec3::Z);
+    }
+
+    #[test]
+    fn stop_key_stops_selected_units_without_panning() {
+        let (orders, movement) = press_stop_key(true);
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].kind, OrderKind::Stop);
+        assert_eq!(movement, Vec3::ZERO);
+    }
+
+    #[test]
+    fn patrol_engages_enemy_until_destroyed_then_goes_on() {
+        let mut app = App::new();
+        app
+            .add_event::<Alert>()
+            .init_resource::<Time>()
+            .init_resource::<SpatialIndex>()
+            .init_resource::<Assets<UnitDef>>()
+            .add_systems(Update, (execute_orders, fire_at_engaged_enemies, remove_destroyed_units).chain());
+
+        let patrol_point = Vec3::new(50.0, 0.0, 0.0);
+        let mut queue = OrderQueue::default();
+        queue.replace(Order::Patrol(patrol_point));
+        let unit = app.world_mut().spawn((Transform::IDENTITY, LOCAL_PLAYER, queue, MovableUnit::default())).id();
+        let enemy_position = Vec3::new(5.0, 0.0, 0.0);
+        let enemy = app.world_mut().spawn((
+            Transform::from_translation(enemy_position),
+            Owner(1),
+            OrderQueue::default(),
+            MovableUnit::default(),
+            Health::full(DEFAULT_DAMAGE_PER_SECOND),
+        )).id();
+        let mut index = app.world_mut().resource_mut::<SpatialIndex>();
+        index.update(unit, Vec2::ZERO);
+        index.update(enemy, enemy_position.xz());
+
+        // The enemy in range stops the patrol
+        app.update();
+        let world = app.world();
+        assert_eq!(world.get::<OrderQueue>(unit).unwrap().engaged, Some(enemy));
+        assert_eq!(world.get::<MovableUnit>(unit).unwrap().destination, None);
+        assert_eq!(world.get::<MovableUnit>(unit).unwrap().face_towards, Some(enemy_position));
+
+        // Two seconds of fire are enough to destroy it
+        app.world_mut().resource_mut::<Time>().advance_by(std::time::Duration::from_secs(2));
+        app.update();
+        assert!(app.world().get_entity(enemy).is_none());
+
+        // The patrol resumes towards its point
+        app.update();
+        let world = app.world();
+        let queue = world.get::<OrderQueue>(unit).unwrap();
+        assert_eq!(queue.engaged, None);
+        assert_eq!(queue.state, OrderState::Patrolling { from: Vec3::ZERO, to: patrol_point });
+        assert_eq!(world.get::<MovableUnit>(unit).unwrap().destination, Some(patrol_point));
+
+        // Having arrived, it turns back
+        app.world_mut().get_mut::<MovableUnit>(unit).unwrap().destination = None;
+        app.update();
+        let world = app.world();
+        assert_eq!(world.get::<OrderQueue>(unit).unwrap().state, OrderState::Patrolling { from: patrol_point, to: Vec3::ZERO });
+        assert_eq!(world.get::<MovableUnit>(unit).unwrap().destination, Some(Vec3::ZERO));
+    }
+
+    #[test]
+    fn unit_order_survives_serialization() {
+        let order = UnitOrder {
+            units: vec![Entity::from_raw(3), Entity::from_raw(7)],
+            kind: OrderKind::Follow,
+            target: OrderTarget::Unit(Entity::from_raw(12)),
+            queued: true,
+        };
+        let json = serde_json::to_string(&order).unwrap();
+        assert_eq!(serde_json::from_str::<UnitOrder>(&json).unwrap(), order);
+
+        let order = UnitOrder { units: vec![Entity::from_raw(1)], kind: OrderKind::Patrol, target: OrderTarget::Point(Vec3::new(1.0, 2.0, -3.5)), queued: false };
+        let json = serde_json::to_string(&order).unwrap();
+        assert_eq!(serde_json::from_str::<UnitOrder>(&json).unwrap(), order);
+    }
+}
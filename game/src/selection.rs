@@ -1,7 +1,20 @@
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::{math::bounding::{Aabb3d, BoundingVolume}, prelude::*, window::PrimaryWindow};
 use bevy_panorbit_camera::PanOrbitCamera;
 
-use crate::{debug::DebugDrawPoint, terrain::MyGroundPlane, units::{MovableUnit, SelectedUnits}, util::{point_2d::Trapez, projection::project_on_terrain}};
+use crate::{debug::DebugDrawPoint, economy::{Building, ResourceNode}, spatial::SpatialIndex, terrain::TerrainHeightmap, units::{MovableUnit, Owner, Selected, SelectedUnits, UnitType, LOCAL_PLAYER}, util::{picking::pick_entity, point_2d::{rect_overlap_fraction, ConvexPolygon, Polygon}, projection::{is_in_viewport, project_bounds_to_viewport, project_circle_to_viewport, project_on_terrain}}};
+
+/// Boxes smaller than this (in pixels) are treated as a plain click
+const DRAG_THRESHOLD: f32 = 4.0;
+/// Two clicks on the same unit within this time (seconds) select all units of its type
+const DOUBLE_CLICK_TIME: f32 = 0.3;
+/// Part of a unit's on-screen bounds that has to be inside the selection box to select the unit
+/// (in addition to units whose center is inside the box)
+const MIN_BOX_OVERLAP: f32 = 0.3;
+/// Holding this key while dragging draws a free-form lasso instead of a box
+const LASSO_KEY: KeyCode = KeyCode::KeyL;
+/// Minimal cursor movement (in pixels) before the next lasso point is recorded
+const LASSO_POINT_SPACING: f32 = 4.0;
+const LASSO_DOT_SIZE: f32 = 3.0;
 
 pub struct MySelectionPlugin;
 
@@ -9,50 +22,190 @@ impl Plugin for MySelectionPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<SelectedUnits>()
+            .add_event::<SelectionClickCompleted>()
+            .add_event::<SelectionLassoCompleted>()
+            .add_event::<SelectionChanged>()
             .add_systems(Update, select_box)
-            .add_systems(Update, perform_selection);
+            .add_systems(Update, select_lasso)
+            .add_systems(Update, perform_selection)
+            .add_systems(Update, perform_click_selection)
+            .add_systems(PostUpdate, sync_selection);
     }
 }
 
 #[derive(Component)]
-struct SelectionBoxInProcess {
+pub struct SelectionBoxInProcess {
     pub x: f32,
     pub y: f32,
 }
 
+/// Root of the lasso overlay, every recorded point is drawn as a child dot
+#[derive(Component)]
+pub struct SelectionLassoInProcess {
+    pub points: Vec<Vec2>,
+}
+
+/// Selection box in the viewport coordinates (logical pixels)
+#[derive(Event, Debug, Clone)]
+pub struct SelectionBoxCompleted(pub Rect, pub SelectionMode);
+
+/// Lasso path in the viewport coordinates (logical pixels)
+#[derive(Event, Debug, Clone)]
+pub struct SelectionLassoCompleted(pub Polygon, pub SelectionMode);
+
+/// Sent once per frame when the selection changed, entities are in the order they were (de)selected
+#[derive(Event, Debug, Clone, Default)]
+pub struct SelectionChanged {
+    pub added: Vec<Entity>,
+    /// Includes entities that were despawned while selected
+    pub removed: Vec<Entity>,
+}
+
+/// Left click without dragging, holds the ray from the camera through the cursor
 #[derive(Event, Debug, Clone)]
-pub struct SelectionBoxCompleted(pub Trapez);
+pub struct SelectionClickCompleted(pub Ray3d, pub SelectionMode);
+
+/// Which entities win when a selection area covers several kinds at once, lower goes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SelectionPriority {
+    OwnUnit,
+    OwnBuilding,
+    /// Enemy and neutral entities, they can only be inspected one at a time
+    Foreign,
+}
+
+/// Something that can be selected
+struct SelectionCandidate {
+    entity: Entity,
+    /// Center of the footprint on the ground
+    position: Vec3,
+    /// Footprint radius
+    radius: f32,
+    bounds: Aabb3d,
+    priority: SelectionPriority,
+}
+
+/// Everything that can be selected, with its footprint, bounds and priority
+fn selection_candidates<'a>(
+    units: impl Iterator<Item = (Entity, &'a Transform, &'a MovableUnit, &'a Owner)>,
+    buildings_q: &Query<(Entity, &Transform, &Building, &Owner)>,
+    nodes_q: &Query<(Entity, &Transform, &ResourceNode)>,
+) -> Vec<SelectionCandidate> {
+    let units = units.map(|(entity, tr, unit, owner)| {
+        let priority = if *owner == LOCAL_PLAYER { SelectionPriority::OwnUnit } else { SelectionPriority::Foreign };
+        SelectionCandidate { entity, position: tr.translation, radius: unit.half_size, bounds: unit.bounds(tr.translation), priority }
+    });
+    let buildings = buildings_q.iter().map(|(entity, tr, building, owner)| {
+        let priority = if *owner == LOCAL_PLAYER { SelectionPriority::OwnBuilding } else { SelectionPriority::Foreign };
+        SelectionCandidate { entity, position: tr.translation, radius: building.half_size, bounds: building.bounds(tr.translation), priority }
+    });
+    let nodes = nodes_q.iter().map(|(entity, tr, node)| {
+        SelectionCandidate { entity, position: tr.translation, radius: node.half_size, bounds: node.bounds(tr.translation), priority: SelectionPriority::Foreign }
+    });
+    units.chain(buildings).chain(nodes).collect()
+}
+
+/// Combines picked entities with the selection so that own and foreign entities never mix.
+/// Foreign entities always replace the selection and make it read-only.
+fn apply_selection(selected_units: &mut SelectedUnits, mode: SelectionMode, picked: &[Entity], read_only: bool) {
+    match mode {
+        SelectionMode::Subtract => mode.apply(selected_units, picked),
+        _ if read_only || selected_units.is_read_only() => selected_units.set(picked, read_only),
+        mode => mode.apply(selected_units, picked),
+    }
+}
+
+/// How newly picked units are combined with the current selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionMode {
+    #[default]
+    Replace,
+    /// Shift
+    Add,
+    /// Ctrl
+    Toggle,
+    /// Alt
+    Subtract,
+}
+
+impl SelectionMode {
+    pub fn from_modifiers(keyboard_input: &ButtonInput<KeyCode>) -> SelectionMode {
+        if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            SelectionMode::Add
+        } else if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+            SelectionMode::Toggle
+        } else if keyboard_input.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) {
+            SelectionMode::Subtract
+        } else {
+            SelectionMode::Replace
+        }
+    }
+
+    pub fn apply(&self, selection: &mut SelectedUnits, picked: &[Entity]) {
+        match self {
+            SelectionMode::Replace => selection.set(picked, selection.is_read_only()),
+            SelectionMode::Add => {
+                for entity in picked {
+                    selection.insert(*entity);
+                }
+            }
+            SelectionMode::Toggle => {
+                for entity in picked {
+                    if !selection.remove(*entity) {
+                        selection.insert(*entity);
+                    }
+                }
+            }
+            SelectionMode::Subtract => {
+                for entity in picked {
+                    selection.remove(*entity);
+                }
+            }
+        }
+    }
+}
 
+#[allow(clippy::too_many_arguments)]
 fn select_box(
     mut commands: Commands,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
     mut select_box_query: Query<(Entity, &mut Style, &SelectionBoxInProcess)>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>, // MyGameCamera
-    q_plane: Query<&GlobalTransform, With<MyGroundPlane>>,
-    mut producer: EventWriter<SelectionBoxCompleted>
+    heightmap: Res<TerrainHeightmap>,
+    mut producer: EventWriter<SelectionBoxCompleted>,
+    mut click_producer: EventWriter<SelectionClickCompleted>,
+    mut debug_point_writer: EventWriter<DebugDrawPoint>,
 ) {
     if let Ok((entity, mut style, center)) = select_box_query.get_single_mut() {
         if mouse_input.just_released(MouseButton::Left) {
             commands.entity(entity).despawn();
 
             let (camera, camera_transform) = q_camera.single();
-            let ground_transform = q_plane.single();
+            let mode = SelectionMode::from_modifiers(&keyboard_input);
 
-            let Some(top_left) = project_on_terrain(Vec2::new(style.left.get_px(), style.top.get_px()), &camera, &camera_transform, &ground_transform) else {
-                return;
-            };
-            let Some(top_right) = project_on_terrain(Vec2::new(style.left.get_px() + style.width.get_px(), style.top.get_px()), &camera, &camera_transform, &ground_transform) else {
-                return;
-            };
-            let Some(bottom_right) = project_on_terrain(Vec2::new(style.left.get_px() + style.width.get_px(), style.top.get_px() + style.height.get_px()), &camera, &camera_transform, &ground_transform) else {
-                return;
-            };
-            let Some(bottom_left) = project_on_terrain(Vec2::new(style.left.get_px(), style.top.get_px() + style.height.get_px()), &camera, &camera_transform, &ground_transform) else {
+            if style.width.get_px() < DRAG_THRESHOLD && style.height.get_px() < DRAG_THRESHOLD {
+                if let Some(ray) = camera.viewport_to_world(camera_transform, Vec2::new(center.x, center.y)) {
+                    click_producer.send(SelectionClickCompleted(ray, mode));
+                }
                 return;
-            };
+            }
+
+            let selection = Rect::new(
+                style.left.get_px(),
+                style.top.get_px(),
+                style.left.get_px() + style.width.get_px(),
+                style.top.get_px() + style.height.get_px(),
+            );
+
+            for corner in ConvexPolygon::from_rect(selection).vertices() {
+                if let Some(point) = project_on_terrain(*corner, camera, camera_transform, &heightmap) {
+                    debug_point_writer.send(DebugDrawPoint(point));
+                }
+            }
 
-            producer.send(SelectionBoxCompleted (Trapez { top_left, top_right, bottom_left, bottom_right}));
+            producer.send(SelectionBoxCompleted(selection, mode));
             return;
         }
         let window = q_window.single();
@@ -75,7 +228,7 @@ fn select_box(
         }
         
         
-    } else if mouse_input.just_pressed(MouseButton::Left) {
+    } else if mouse_input.just_pressed(MouseButton::Left) && !keyboard_input.pressed(LASSO_KEY) {
         let window = q_window.single();
         let Some(cursor_position) = window.cursor_position() else {
             return;
@@ -100,29 +253,289 @@ fn select_box(
     }
 }
 
+/// Records the cursor path while the left button is held together with [`LASSO_KEY`].
+/// On release the path is closed into a polygon, a short path is treated as a click.
+#[allow(clippy::too_many_arguments)]
+fn select_lasso(
+    mut commands: Commands,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut lasso_query: Query<(Entity, &mut SelectionLassoInProcess)>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    mut producer: EventWriter<SelectionLassoCompleted>,
+    mut click_producer: EventWriter<SelectionClickCompleted>,
+) {
+    let window = q_window.single();
+
+    if let Ok((entity, mut lasso)) = lasso_query.get_single_mut() {
+        if mouse_input.just_released(MouseButton::Left) {
+            commands.entity(entity).despawn_recursive();
+
+            let mode = SelectionMode::from_modifiers(&keyboard_input);
+            let extent = lasso.points.iter()
+                .fold(Rect::EMPTY, |rect, point| rect.union_point(*point))
+                .size();
+            if lasso.points.len() < 3 || extent.max_element() < DRAG_THRESHOLD {
+                let (camera, camera_transform) = q_camera.single();
+                if let Some(ray) = camera.viewport_to_world(camera_transform, lasso.points[0]) {
+                    click_producer.send(SelectionClickCompleted(ray, mode));
+                }
+                return;
+            }
+
+            producer.send(SelectionLassoCompleted(Polygon::new(std::mem::take(&mut lasso.points)), mode));
+            return;
+        }
+
+        let Some(cursor_position) = window.cursor_position() else {
+            return;
+        };
+        if lasso.points.last().is_some_and(|last| last.distance(cursor_position) < LASSO_POINT_SPACING) {
+            return;
+        }
+        lasso.points.push(cursor_position);
+        let dot = spawn_lasso_dot(&mut commands, cursor_position);
+        commands.entity(entity).add_child(dot);
+    } else if mouse_input.just_pressed(MouseButton::Left) && keyboard_input.pressed(LASSO_KEY) {
+        let Some(cursor_position) = window.cursor_position() else {
+            return;
+        };
+
+        let dot = spawn_lasso_dot(&mut commands, cursor_position);
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                SelectionLassoInProcess { points: vec![cursor_position] },
+            ))
+            .add_child(dot);
+    }
+}
+
+fn spawn_lasso_dot(commands: &mut Commands, position: Vec2) -> Entity {
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Px(LASSO_DOT_SIZE),
+            height: Val::Px(LASSO_DOT_SIZE),
+            left: Val::Px(position.x - LASSO_DOT_SIZE / 2.0),
+            top: Val::Px(position.y - LASSO_DOT_SIZE / 2.0),
+            ..Default::default()
+        },
+        background_color: BackgroundColor(Color::Srgba(Srgba { red: 0.21960784, green: 0.7411765, blue: 0.972549, alpha: 0.8 })),
+        ..Default::default()
+    }).id()
+}
+
+/// Selects entities that, as seen by the camera, fall into the selection box or lasso.
+/// Works in the screen space, so it doesn't depend on the camera angle or the terrain height.
+/// Only the kind with the highest [`SelectionPriority`] found in the area gets selected.
+#[allow(clippy::too_many_arguments)]
 fn perform_selection(
     mut selected_units: ResMut<SelectedUnits>,
-    units_q: Query<(&Transform, Entity), With<MovableUnit>>,
-    mut reader: EventReader<SelectionBoxCompleted>,
-    mut debug_point_writer: EventWriter<DebugDrawPoint>,
+    units_q: Query<(Entity, &Transform, &MovableUnit, &Owner)>,
+    buildings_q: Query<(Entity, &Transform, &Building, &Owner)>,
+    nodes_q: Query<(Entity, &Transform, &ResourceNode)>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    heightmap: Res<TerrainHeightmap>,
+    spatial_index: Res<SpatialIndex>,
+    mut box_reader: EventReader<SelectionBoxCompleted>,
+    mut lasso_reader: EventReader<SelectionLassoCompleted>,
+) {
+    let (camera, camera_transform) = q_camera.single();
+
+    // `vertices` outline the selection area in the viewport,
+    // `in_area` tells whether the candidate is inside the area
+    let picked_entities = |vertices: &[Vec2], in_area: &dyn Fn(&SelectionCandidate) -> bool| -> (Vec<Entity>, bool) {
+        // Only units near the area projected on the ground need the exact check,
+        // unless some of its corners point above the horizon
+        let rays: Option<Vec<Ray3d>> = vertices.iter()
+            .map(|vertex| camera.viewport_to_world(camera_transform, *vertex))
+            .collect();
+        let (ground_height, top_height) = heightmap.height_range();
+        let max_half_size = units_q.iter().map(|(_, _, unit, _)| unit.half_size).fold(0.0, f32::max);
+        let ground_area = rays.and_then(|rays| {
+            // Unit bounds reach `half_size` up and `half_size * sqrt(2)` sideways at their corners
+            selection_ground_area(&rays, ground_height, top_height - ground_height + max_half_size, max_half_size * std::f32::consts::SQRT_2)
+        });
+        let units: Vec<_> = match ground_area {
+            Some((outline, margin)) => units_q.iter_many(spatial_index.query_polygon(&outline, margin)).collect(),
+            None => units_q.iter().collect(),
+        };
+
+        let mut picked: Vec<SelectionCandidate> = selection_candidates(units.into_iter(), &buildings_q, &nodes_q).into_iter()
+            .filter(|candidate| in_area(candidate))
+            .collect();
+
+        let Some(best) = picked.iter().map(|candidate| candidate.priority).min() else {
+            return (Vec::new(), false);
+        };
+        picked.retain(|candidate| candidate.priority == best);
+        if best == SelectionPriority::Foreign {
+            // Inspect the one closest to the camera
+            let camera_position = camera_transform.translation();
+            picked.sort_by(|a, b| {
+                let distance = |bounds: &Aabb3d| Vec3::from(bounds.center()).distance_squared(camera_position);
+                distance(&a.bounds).total_cmp(&distance(&b.bounds))
+            });
+            picked.truncate(1);
+        }
+        (picked.into_iter().map(|candidate| candidate.entity).collect(), best == SelectionPriority::Foreign)
+    };
+
+    for SelectionBoxCompleted(selection, mode) in box_reader.read() {
+        // The center is inside the box or a good part of the bounds is
+        let polygon = ConvexPolygon::from_rect(*selection);
+        let in_box = |candidate: &SelectionCandidate| {
+            let center_inside = camera.world_to_viewport(camera_transform, candidate.position)
+                .is_some_and(|center| polygon.contains(center));
+            center_inside || project_bounds_to_viewport(&candidate.bounds, camera, camera_transform)
+                .is_some_and(|bounds| rect_overlap_fraction(bounds, *selection) >= MIN_BOX_OVERLAP)
+        };
+        let (picked, read_only) = picked_entities(polygon.vertices(), &in_box);
+        apply_selection(&mut selected_units, *mode, &picked, read_only);
+    }
+
+    for SelectionLassoCompleted(polygon, mode) in lasso_reader.read() {
+        // The lasso touches the footprint circle
+        let in_lasso = |candidate: &SelectionCandidate| {
+            project_circle_to_viewport(candidate.position, candidate.radius, camera, camera_transform)
+                .is_some_and(|(center, radius)| polygon.overlaps_circle(center, radius))
+        };
+        let (picked, read_only) = picked_entities(polygon.vertices(), &in_lasso);
+        apply_selection(&mut selected_units, *mode, &picked, read_only);
+    }
+}
+
+/// Outline of the selection area on the ground plane at `ground_height` together with the margin around it
+/// that covers every unit the screen-space test can pick. `rays` go from the camera through the area's vertices.
+///
+/// Anything up to `max_elevation` above the ground is seen where its ray hits the ground further away,
+/// the shallower the ray the further. `max_reach` is the largest horizontal distance of a unit's bounds
+/// from its position. `None` when some ray doesn't hit the ground, i.e. the area reaches above the horizon.
+fn selection_ground_area(rays: &[Ray3d], ground_height: f32, max_elevation: f32, max_reach: f32) -> Option<(Polygon, f32)> {
+    let mut outline = Vec::with_capacity(rays.len());
+    // Rays inside the area are never shallower than the shallowest one through a vertex
+    let mut min_slope = f32::INFINITY;
+    for ray in rays {
+        if ray.direction.y >= 0.0 {
+            return None;
+        }
+        let distance = ray.intersect_plane(Vec3::Y * ground_height, InfinitePlane3d::new(Vec3::Y))?;
+        outline.push(ray.get_point(distance).xz());
+        min_slope = min_slope.min(-ray.direction.y / ray.direction.xz().length());
+    }
+    Some((Polygon::new(outline), max_elevation / min_slope + max_reach))
+}
+
+/// Selects the entity under the cursor, enemy and neutral ones are selected read-only.
+/// Double click on an own unit, with or without Ctrl, selects all own units of the same type visible on the screen,
+/// a single Ctrl+click toggles just the clicked unit.
+#[allow(clippy::too_many_arguments)]
+fn perform_click_selection(
+    mut selected_units: ResMut<SelectedUnits>,
+    units_q: Query<(Entity, &Transform, &MovableUnit, &Owner)>,
+    types_q: Query<&UnitType>,
+    buildings_q: Query<(Entity, &Transform, &Building, &Owner)>,
+    nodes_q: Query<(Entity, &Transform, &ResourceNode)>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    time: Res<Time>,
+    mut last_click: Local<Option<(Entity, f32)>>,
+    mut reader: EventReader<SelectionClickCompleted>,
 ) {
-    for SelectionBoxCompleted(selection) in reader.read() {
-        selected_units.unit_entities.clear();
-        println!("\nSelection box: {} - {}", selection.top_left, selection.bottom_right);
-        for (unit_trans, unit_entity) in units_q.iter() {
-            if selection.contains(unit_trans.translation) {
-                selected_units.unit_entities.push(unit_entity);
-                println!("Unit {} is in selection: TRUE",  unit_trans.translation);
-            } else {
-                println!("Unit {} is in selection: FALSE",  unit_trans.translation);
+    for SelectionClickCompleted(ray, mode) in reader.read() {
+        let candidates = selection_candidates(units_q.iter(), &buildings_q, &nodes_q);
+        let picked = pick_entity(*ray, candidates.iter().map(|candidate| (candidate.entity, candidate.bounds)));
+        let priority = picked
+            .and_then(|entity| candidates.iter().find(|candidate| candidate.entity == entity))
+            .map(|candidate| candidate.priority);
+
+        let now = time.elapsed_seconds();
+        let is_double_click = last_click
+            .is_some_and(|(last_entity, last_time)| Some(last_entity) == picked && now - last_time < DOUBLE_CLICK_TIME);
+        *last_click = picked.map(|entity| (entity, now));
+
+        let clicked_own_type = picked
+            .filter(|_| priority == Some(SelectionPriority::OwnUnit))
+            .and_then(|entity| types_q.get(entity).ok());
+
+        match (clicked_own_type, same_type_selection_mode(*mode, is_double_click)) {
+            (Some(unit_type), Some(type_mode)) => {
+                let (camera, camera_transform) = q_camera.single();
+                let same_type: Vec<Entity> = units_q.iter()
+                    .filter(|(entity, tr, _, owner)| {
+                        **owner == LOCAL_PLAYER
+                            && types_q.get(*entity).is_ok_and(|other_type| other_type == unit_type)
+                            && is_in_viewport(tr.translation, camera, camera_transform)
+                    })
+                    .map(|(entity, _, _, _)| entity)
+                    .collect();
+                apply_selection(&mut selected_units, type_mode, &same_type, false);
             }
+            _ => {
+                let picked: Vec<Entity> = picked.into_iter().collect();
+                apply_selection(&mut selected_units, *mode, &picked, priority == Some(SelectionPriority::Foreign));
+            }
+        }
+    }
+}
+
+/// How a click on an own unit applies all own units of its type, `None` when it picks the unit alone.
+/// Shift+double click and Ctrl+double click add the type to the selection, toggling it would undo the first click.
+fn same_type_selection_mode(mode: SelectionMode, is_double_click: bool) -> Option<SelectionMode> {
+    match mode {
+        _ if !is_double_click => None,
+        SelectionMode::Toggle => Some(SelectionMode::Add),
+        _ => Some(mode),
+    }
+}
+
+/// Drops despawned entities from the selection, moves the [`Selected`] markers after it
+/// and reports what changed.
+fn sync_selection(
+    mut commands: Commands,
+    mut selected_units: ResMut<SelectedUnits>,
+    existing_q: Query<()>,
+    marked_q: Query<Entity, With<Selected>>,
+    mut writer: EventWriter<SelectionChanged>,
+) {
+    let despawned: Vec<Entity> = selected_units.entities().iter()
+        .filter(|entity| !existing_q.contains(**entity))
+        .copied()
+        .collect();
+    for entity in despawned.iter() {
+        selected_units.remove(*entity);
+    }
+
+    if !selected_units.is_changed() {
+        return;
+    }
 
-            debug_point_writer.send(DebugDrawPoint(selection.top_left));
-            debug_point_writer.send(DebugDrawPoint(selection.top_right));
-            debug_point_writer.send(DebugDrawPoint(selection.bottom_right));
-            debug_point_writer.send(DebugDrawPoint(selection.bottom_left));
+    let mut changed = SelectionChanged { removed: despawned, ..default() };
+    for entity in marked_q.iter() {
+        if !selected_units.contains(entity) {
+            commands.entity(entity).remove::<Selected>();
+            changed.removed.push(entity);
         }
     }
+    for entity in selected_units.entities() {
+        if !marked_q.contains(*entity) {
+            commands.entity(*entity).insert(Selected);
+            changed.added.push(*entity);
+        }
+    }
+
+    if !changed.added.is_empty() || !changed.removed.is_empty() {
+        writer.send(changed);
+    }
 }
 
 trait ValPx {
@@ -137,3 +550,70 @@ impl ValPx for Val {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ground_area_covers_elevated_unit_seen_at_a_low_angle() {
+        // Camera looking down at the lowest pitch, the unit stands on a plateau 3 units high
+        let camera = Vec3::new(0.0, 8.0, 20.0);
+        let (position, half_size) = (Vec3::new(0.0, 3.0, -10.0), 2.0);
+        let unit_top = position + Vec3::Y * half_size;
+
+        // Small selection area around the top of the unit
+        let view = Transform::from_translation(camera).looking_at(unit_top, Vec3::Y);
+        let rays: Vec<Ray3d> = [(-0.01, -0.01), (0.01, -0.01), (0.01, 0.01), (-0.01, 0.01)].iter()
+            .map(|(yaw, pitch)| {
+                let direction = Quat::from_euler(EulerRot::YXZ, *yaw, *pitch, 0.0) * *view.forward();
+                Ray3d::new(camera, direction)
+            })
+            .collect();
+
+        let (outline, margin) = selection_ground_area(&rays, 0.0, 3.0 + half_size, half_size * std::f32::consts::SQRT_2).unwrap();
+        let mut index = SpatialIndex::default();
+        let unit = Entity::from_raw(1);
+        index.update(unit, position.xz());
+        assert_eq!(index.query_polygon(&outline, margin), vec![unit]);
+        // The area seen on the ground is far behind the unit
+        assert!(!outline.overlaps_circle(position.xz(), 10.0));
+    }
+
+    #[test]
+    fn ground_area_is_none_above_the_horizon() {
+        let rays = [Ray3d::new(Vec3::Y, Vec3::new(0.0, -1.0, -1.0)), Ray3d::new(Vec3::Y, Vec3::new(0.0, 0.1, -1.0))];
+        assert!(selection_ground_area(&rays, 0.0, 1.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn ctrl_click_toggles_unit_and_ctrl_double_click_adds_its_type() {
+        assert_eq!(same_type_selection_mode(SelectionMode::Replace, true), Some(SelectionMode::Replace));
+        assert_eq!(same_type_selection_mode(SelectionMode::Add, true), Some(SelectionMode::Add));
+        assert_eq!(same_type_selection_mode(SelectionMode::Toggle, true), Some(SelectionMode::Add));
+        assert_eq!(same_type_selection_mode(SelectionMode::Toggle, false), None);
+        assert_eq!(same_type_selection_mode(SelectionMode::Replace, false), None);
+
+        // Tanks a and c share a type, b is another unit
+        let (a, b, c) = (Entity::from_raw(1), Entity::from_raw(2), Entity::from_raw(3));
+        let mut selected = SelectedUnits::default();
+        selected.set(&[a, b], false);
+        let ctrl_click = |selected: &mut SelectedUnits, is_double_click: bool| {
+            match same_type_selection_mode(SelectionMode::Toggle, is_double_click) {
+                Some(mode) => apply_selection(selected, mode, &[a, c], false),
+                None => apply_selection(selected, SelectionMode::Toggle, &[a], false),
+            }
+        };
+
+        // Single Ctrl+click toggles the clicked unit alone
+        ctrl_click(&mut selected, false);
+        assert_eq!(selected.entities(), &[b]);
+        ctrl_click(&mut selected, false);
+        assert_eq!(selected.entities(), &[b, a]);
+
+        // Ctrl+double click brings in every unit of the type and keeps the rest
+        ctrl_click(&mut selected, false);
+        ctrl_click(&mut selected, true);
+        assert_eq!(selected.entities(), &[b, a, c]);
+    }
+}
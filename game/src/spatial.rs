@@ -0,0 +1,196 @@
+use bevy::{ecs::entity::EntityHashMap, prelude::*, utils::HashMap};
+
+use crate::{units::MovableUnit, util::point_2d::Polygon};
+
+/// Side of a grid cell in world units, about the size of a couple of tanks
+const CELL_SIZE: f32 = 8.0;
+
+pub struct MySpatialPlugin;
+
+impl Plugin for MySpatialPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SpatialIndex>()
+            .add_systems(PreUpdate, update_spatial_index);
+    }
+}
+
+/// Uniform grid of units on the XZ plane for neighbour and area queries.
+///
+/// Kept up to date at the start of every frame, so it sees the positions units had after the previous frame.
+#[derive(Resource, Debug)]
+pub struct SpatialIndex {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<Entity>>,
+    positions: EntityHashMap<Vec2>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex::new(CELL_SIZE)
+    }
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f32) -> SpatialIndex {
+        SpatialIndex { cell_size, cells: default(), positions: default() }
+    }
+
+    fn cell(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Inserts the entity or moves it to the new position
+    pub fn update(&mut self, entity: Entity, position: Vec2) {
+        let cell = self.cell(position);
+        if let Some(old_position) = self.positions.insert(entity, position) {
+            let old_cell = self.cell(old_position);
+            if old_cell == cell {
+                return;
+            }
+            self.remove_from_cell(old_cell, entity);
+        }
+        self.cells.entry(cell).or_default().push(entity);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(position) = self.positions.remove(&entity) {
+            self.remove_from_cell(self.cell(position), entity);
+        }
+    }
+
+    fn remove_from_cell(&mut self, cell: IVec2, entity: Entity) {
+        if let Some(entities) = self.cells.get_mut(&cell) {
+            entities.retain(|e| *e != entity);
+            if entities.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    /// Entities inside the rectangle (or on its border)
+    pub fn query_rect(&self, rect: Rect) -> Vec<Entity> {
+        if rect.is_empty() {
+            return Vec::new();
+        }
+        let in_rect = |entity: &&Entity| rect.contains(self.positions[*entity]);
+
+        let min = self.cell(rect.min);
+        let max = self.cell(rect.max);
+        let covered_cells = (max.x - min.x + 1) as i64 * (max.y - min.y + 1) as i64;
+        // For huge rectangles it's cheaper to go through the occupied cells only
+        if covered_cells > self.cells.len() as i64 {
+            return self.cells.values().flatten().filter(in_rect).copied().collect();
+        }
+
+        let mut result = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                if let Some(entities) = self.cells.get(&IVec2::new(x, y)) {
+                    result.extend(entities.iter().filter(in_rect));
+                }
+            }
+        }
+        result
+    }
+
+    /// Entities within the given distance from the center
+    pub fn query_circle(&self, center: Vec2, radius: f32) -> Vec<Entity> {
+        let mut result = self.query_rect(Rect::from_center_half_size(center, Vec2::splat(radius)));
+        result.retain(|entity| self.positions[entity].distance(center) <= radius);
+        result
+    }
+
+    /// Entities inside the polygon or not further than `margin` from its border
+    pub fn query_polygon(&self, polygon: &Polygon, margin: f32) -> Vec<Entity> {
+        let mut result = self.query_rect(polygon.bounding_rect().inflate(margin));
+        result.retain(|entity| polygon.overlaps_circle(self.positions[entity], margin));
+        result
+    }
+
+    /// Up to `k` entities accepted by the filter within `max_distance`, the nearest first
+    pub fn k_nearest(&self, point: Vec2, k: usize, max_distance: f32, filter: impl Fn(Entity) -> bool) -> Vec<Entity> {
+        let mut result = self.query_circle(point, max_distance);
+        result.retain(|entity| filter(*entity));
+        result.sort_by(|a, b| {
+            self.positions[a].distance_squared(point).total_cmp(&self.positions[b].distance_squared(point))
+        });
+        result.truncate(k);
+        result
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_spatial_index(
+    mut index: ResMut<SpatialIndex>,
+    moved_q: Query<(Entity, &Transform), (With<MovableUnit>, Changed<Transform>)>,
+    mut removed: RemovedComponents<MovableUnit>,
+) {
+    for entity in removed.read() {
+        index.remove(entity);
+    }
+    for (entity, tr) in moved_q.iter() {
+        index.update(entity, tr.translation.xz());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_with(positions: &[(f32, f32)]) -> (SpatialIndex, Vec<Entity>) {
+        let mut index = SpatialIndex::new(CELL_SIZE);
+        let entities: Vec<Entity> = (0..positions.len() as u32).map(Entity::from_raw).collect();
+        for (entity, (x, y)) in entities.iter().zip(positions) {
+            index.update(*entity, Vec2::new(*x, *y));
+        }
+        (index, entities)
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn moved_and_removed_entities_leave_their_cells() {
+        let (mut index, e) = index_with(&[(1.0, 1.0), (2.0, 2.0)]);
+        index.update(e[0], Vec2::new(50.0, 50.0));
+        assert_eq!(index.query_rect(Rect::new(0.0, 0.0, 5.0, 5.0)), vec![e[1]]);
+        assert_eq!(index.query_rect(Rect::new(45.0, 45.0, 55.0, 55.0)), vec![e[0]]);
+
+        index.remove(e[1]);
+        assert!(index.query_rect(Rect::new(0.0, 0.0, 5.0, 5.0)).is_empty());
+        assert_eq!(index.cells.len(), 1);
+    }
+
+    #[test]
+    fn rect_query_spans_cells_and_negative_coordinates() {
+        let (index, e) = index_with(&[(-20.0, -3.0), (-1.0, 1.0), (15.0, 7.0), (30.0, 0.0)]);
+        assert_eq!(sorted(index.query_rect(Rect::new(-25.0, -5.0, 20.0, 10.0))), vec![e[0], e[1], e[2]]);
+        assert_eq!(sorted(index.query_rect(Rect::new(-1e9, -1e9, 1e9, 1e9))), e);
+        assert!(index.query_rect(Rect::EMPTY).is_empty());
+    }
+
+    #[test]
+    fn circle_query_checks_the_distance() {
+        let (index, e) = index_with(&[(0.0, 0.0), (6.0, 0.0), (6.0, 6.0)]);
+        assert_eq!(sorted(index.query_circle(Vec2::ZERO, 6.0)), vec![e[0], e[1]]);
+    }
+
+    #[test]
+    fn polygon_query_includes_the_margin() {
+        let (index, e) = index_with(&[(5.0, 5.0), (12.0, 0.0), (5.0, 20.0)]);
+        let triangle = Polygon::new(vec![Vec2::new(0.0, 0.0), Vec2::new(10.0, 0.0), Vec2::new(0.0, 10.0)]);
+        assert_eq!(sorted(index.query_polygon(&triangle, 0.0)), vec![e[0]]);
+        assert_eq!(sorted(index.query_polygon(&triangle, 3.0)), vec![e[0], e[1]]);
+    }
+
+    #[test]
+    fn k_nearest_sorts_and_filters() {
+        let (index, e) = index_with(&[(9.0, 0.0), (1.0, 0.0), (-4.0, 0.0), (40.0, 0.0)]);
+        assert_eq!(index.k_nearest(Vec2::ZERO, 2, 100.0, |_| true), vec![e[1], e[2]]);
+        assert_eq!(index.k_nearest(Vec2::ZERO, 10, 100.0, |entity| entity != e[1]), vec![e[2], e[0], e[3]]);
+        assert_eq!(index.k_nearest(Vec2::ZERO, 10, 5.0, |_| true), vec![e[1], e[2]]);
+    }
+}
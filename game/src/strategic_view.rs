@@ -0,0 +1,188 @@
+use bevy::{pbr::{wireframe::NoWireframe, NotShadowCaster}, prelude::*, utils::HashMap};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::{terrain::{MyGroundPlane, TerrainMaterials}, units::{MovableUnit, Owner, UnitModel, LOCAL_PLAYER}};
+
+/// Camera height at which units turn into icons
+const STRATEGIC_HEIGHT: f32 = 45.0;
+/// Height range around [`STRATEGIC_HEIGHT`] over which icons fade in and out
+const TRANSITION_HEIGHT: f32 = 10.0;
+/// Icon diameter per unit of the camera distance, keeps the icons the same size on the screen
+const ICON_SIZE: f32 = 0.04;
+
+pub struct MyStrategicViewPlugin;
+
+impl Plugin for MyStrategicViewPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<StrategicView>()
+            .add_systems(Startup, setup_icon_assets)
+            .add_systems(Update, update_strategic_view)
+            .add_systems(Update, spawn_unit_icons)
+            .add_systems(Update, switch_unit_rendering.after(update_strategic_view))
+            .add_systems(Update, place_unit_icons.after(update_strategic_view).after(spawn_unit_icons));
+    }
+}
+
+/// How far the view went into the strategic mode: 0 - 3D models, 1 - icons only
+#[derive(Resource, Default, Debug)]
+pub struct StrategicView {
+    pub blend: f32,
+}
+
+impl StrategicView {
+    /// Past the middle of the transition the detailed terrain is swapped for the overview
+    pub fn is_strategic(&self) -> bool {
+        self.blend >= 0.5
+    }
+}
+
+/// Billboard standing for the unit in the strategic view
+#[derive(Component, Debug)]
+struct UnitIcon {
+    unit: Entity,
+}
+
+#[derive(Resource)]
+struct IconAssets {
+    mesh: Handle<Mesh>,
+    /// One material per player, all icons of a player fade together
+    materials: HashMap<Owner, Handle<StandardMaterial>>,
+}
+
+fn faction_color(owner: Owner) -> Color {
+    if owner == LOCAL_PLAYER {
+        return Color::srgb(0.2, 0.6, 1.0);
+    }
+    const ENEMY_COLORS: [Color; 3] = [Color::srgb(0.9, 0.2, 0.15), Color::srgb(0.95, 0.8, 0.1), Color::srgb(0.7, 0.3, 0.9)];
+    ENEMY_COLORS[(owner.0 as usize - 1) % ENEMY_COLORS.len()]
+}
+
+fn setup_icon_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    commands.insert_resource(IconAssets {
+        mesh: meshes.add(Circle::new(0.5)),
+        materials: HashMap::new(),
+    });
+}
+
+fn update_strategic_view(
+    mut strategic_view: ResMut<StrategicView>,
+    q_camera: Query<&Transform, With<PanOrbitCamera>>,
+) {
+    let height = q_camera.single().translation.y;
+    let blend = ((height - STRATEGIC_HEIGHT) / TRANSITION_HEIGHT + 0.5).clamp(0.0, 1.0);
+    // Smoothstep, so the fade doesn't start and stop abruptly
+    let blend = blend * blend * (3.0 - 2.0 * blend);
+    if strategic_view.blend != blend {
+        strategic_view.blend = blend;
+    }
+}
+
+fn spawn_unit_icons(
+    mut commands: Commands,
+    strategic_view: Res<StrategicView>,
+    mut icon_assets: ResMut<IconAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    new_units_q: Query<(Entity, &Owner), Added<MovableUnit>>,
+) {
+    for (unit, owner) in new_units_q.iter() {
+        let material = icon_assets.materials.entry(*owner).or_insert_with(|| {
+            materials.add(StandardMaterial {
+                base_color: faction_color(*owner).with_alpha(strategic_view.blend),
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            })
+        }).clone();
+
+        commands.spawn((
+            PbrBundle {
+                mesh: icon_assets.mesh.clone(),
+                material,
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            UnitIcon { unit },
+            NotShadowCaster,
+            NoWireframe,
+        ));
+    }
+}
+
+/// Keeps the icons above their units, facing the camera, and fades them with the view
+fn place_unit_icons(
+    mut commands: Commands,
+    strategic_view: Res<StrategicView>,
+    icon_assets: Res<IconAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    q_camera: Query<&Transform, With<PanOrbitCamera>>,
+    units_q: Query<(&Transform, &MovableUnit), Without<UnitIcon>>,
+    mut icons_q: Query<(Entity, &UnitIcon, &mut Transform, &mut Visibility), Without<PanOrbitCamera>>,
+) {
+    if strategic_view.is_changed() {
+        for material in icon_assets.materials.values() {
+            if let Some(material) = materials.get_mut(material) {
+                material.base_color.set_alpha(strategic_view.blend);
+            }
+        }
+    }
+
+    let camera_transform = q_camera.single();
+    for (icon_entity, icon, mut transform, mut visibility) in icons_q.iter_mut() {
+        let Ok((unit_tr, unit)) = units_q.get(icon.unit) else {
+            commands.entity(icon_entity).despawn();
+            continue;
+        };
+
+        let shown = if strategic_view.blend > 0.0 { Visibility::Inherited } else { Visibility::Hidden };
+        if *visibility != shown {
+            *visibility = shown;
+        }
+        if shown == Visibility::Hidden {
+            continue;
+        }
+
+        let position = unit_tr.translation + Vec3::Y * unit.half_size;
+        let size = ICON_SIZE * camera_transform.translation.distance(position);
+        *transform = Transform::from_translation(position)
+            .with_rotation(camera_transform.rotation)
+            .with_scale(Vec3::splat(size));
+    }
+}
+
+/// Shrinks the unit models away as the icons fade in, hidden completely in the full strategic view.
+/// The terrain switches to the plain overview in the middle of the transition.
+fn switch_unit_rendering(
+    strategic_view: Res<StrategicView>,
+    terrain_materials: Res<TerrainMaterials>,
+    mut models_q: Query<(&mut Transform, &mut Visibility), With<UnitModel>>,
+    mut ground_q: Query<&mut Handle<StandardMaterial>, With<MyGroundPlane>>,
+    mut was_strategic: Local<bool>,
+) {
+    let model_scale = 1.0 - strategic_view.blend;
+    let visibility = if model_scale > 0.0 { Visibility::Inherited } else { Visibility::Hidden };
+    // Newly spawned units have to follow the current mode as well.
+    // Only the models change, the units keep their transform and picking bounds.
+    for (mut transform, mut model_visibility) in models_q.iter_mut() {
+        if *model_visibility != visibility {
+            *model_visibility = visibility;
+        }
+        // Models shrink towards the ground, the hidden ones keep their last size
+        if model_scale > 0.0 && transform.scale != Vec3::splat(model_scale) {
+            transform.scale = Vec3::splat(model_scale);
+        }
+    }
+
+    let strategic = strategic_view.is_strategic();
+    if strategic == *was_strategic {
+        return;
+    }
+    *was_strategic = strategic;
+    let material = if strategic { &terrain_materials.overview } else { &terrain_materials.detailed };
+    for mut ground_material in ground_q.iter_mut() {
+        *ground_material = material.clone();
+    }
+}
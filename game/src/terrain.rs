@@ -1,10 +1,15 @@
-use bevy::{pbr::NotShadowCaster, prelude::*, render::texture::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor}};
+use bevy::{pbr::NotShadowCaster, prelude::*, render::{mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology, texture::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor}}};
+
+const TERRAIN_SIZE: f32 = 50.0;
+const TERRAIN_CELL_SIZE: f32 = 1.0;
 
 pub struct MyTerrainPlugin;
 
 impl Plugin for MyTerrainPlugin {
     fn build(&self, app: &mut App) {
+        // Inserted right away so that every startup system can place things on the ground
         app
+            .insert_resource(TerrainHeightmap::from_fn(TERRAIN_SIZE, TERRAIN_CELL_SIZE, terrain_height))
             .add_systems(Startup, setup_terrain);
     }
 }
@@ -12,11 +17,167 @@ impl Plugin for MyTerrainPlugin {
 #[derive(Component)]
 pub struct MyGroundPlane;
 
+/// Looks of the ground: textured for the normal view, plain for the strategic overview
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainMaterials {
+    pub detailed: Handle<StandardMaterial>,
+    pub overview: Handle<StandardMaterial>,
+}
+
+/// Terrain surface represented as a regular grid of heights.
+/// Coordinates are global, the ground entity is expected to stay at the origin.
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainHeightmap {
+    /// X/Z coordinates of the first sample
+    pub origin: Vec2,
+    /// Distance between neighbour samples
+    pub cell_size: f32,
+    /// Number of samples along X
+    pub columns: usize,
+    /// Number of samples along Z
+    pub rows: usize,
+    /// Row-major heights
+    pub heights: Vec<f32>,
+}
+
+impl TerrainHeightmap {
+    /// Builds a square heightmap centered at the origin, sampling heights from the given function.
+    pub fn from_fn(size: f32, cell_size: f32, height: impl Fn(f32, f32) -> f32) -> TerrainHeightmap {
+        let cells = (size / cell_size).round() as usize;
+        let origin = Vec2::splat(-size / 2.0);
+        let mut heights = Vec::with_capacity((cells + 1) * (cells + 1));
+        for row in 0..=cells {
+            for col in 0..=cells {
+                heights.push(height(origin.x + col as f32 * cell_size, origin.y + row as f32 * cell_size));
+            }
+        }
+        TerrainHeightmap { origin, cell_size, columns: cells + 1, rows: cells + 1, heights }
+    }
+
+    /// X/Z area covered by the terrain
+    pub fn extents(&self) -> Rect {
+        let size = Vec2::new((self.columns - 1) as f32, (self.rows - 1) as f32) * self.cell_size;
+        Rect::from_corners(self.origin, self.origin + size)
+    }
+
+    fn sample(&self, col: usize, row: usize) -> f32 {
+        self.heights[row.min(self.rows - 1) * self.columns + col.min(self.columns - 1)]
+    }
+
+    /// Height of the surface at the given point, bilinearly interpolated.
+    /// Points outside of the terrain get the height of the closest edge.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let local = ((Vec2::new(x, z) - self.origin) / self.cell_size)
+            .clamp(Vec2::ZERO, Vec2::new((self.columns - 1) as f32, (self.rows - 1) as f32));
+        let col = local.x.floor() as usize;
+        let row = local.y.floor() as usize;
+        let fract = local - local.floor();
+
+        let top = self.sample(col, row).lerp(self.sample(col + 1, row), fract.x);
+        let bottom = self.sample(col, row + 1).lerp(self.sample(col + 1, row + 1), fract.x);
+        top.lerp(bottom, fract.y)
+    }
+
+    /// Surface normal at the given point
+    pub fn normal_at(&self, x: f32, z: f32) -> Vec3 {
+        let d = self.cell_size;
+        let dx = self.height_at(x + d, z) - self.height_at(x - d, z);
+        let dz = self.height_at(x, z + d) - self.height_at(x, z - d);
+        Vec3::new(-dx, 2.0 * d, -dz).normalize()
+    }
+
+    /// Lowest and highest point of the surface
+    pub fn height_range(&self) -> (f32, f32) {
+        self.heights.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| (min.min(*h), max.max(*h)))
+    }
+
+    /// First point where the ray hits the surface, `None` if the ray doesn't go down.
+    /// Outside of the terrain the surface continues with the height of the closest edge.
+    pub fn raycast(&self, ray: Ray3d) -> Option<Vec3> {
+        if ray.direction.y >= 0.0 {
+            return None;
+        }
+        let (min_height, max_height) = self.height_range();
+        let above = |t: f32| {
+            let point = ray.get_point(t);
+            point.y > self.height_at(point.x, point.z)
+        };
+
+        // The ray can only hit the surface between the highest and the lowest sample
+        let mut from = ((ray.origin.y - max_height) / -ray.direction.y).max(0.0);
+        let to = ((ray.origin.y - min_height) / -ray.direction.y).max(from);
+        if !above(from) {
+            return Some(ray.get_point(from));
+        }
+
+        // March in steps shorter than a cell, so no bump is skipped, then refine by bisection
+        let step = self.cell_size / 2.0;
+        let mut t = from;
+        while t < to {
+            let next = (t + step).min(to);
+            if !above(next) {
+                let mut hit = next;
+                from = t;
+                for _ in 0..16 {
+                    let middle = (from + hit) / 2.0;
+                    if above(middle) { from = middle } else { hit = middle }
+                }
+                return Some(ray.get_point(hit));
+            }
+            t = next;
+        }
+        Some(ray.get_point(to))
+    }
+
+    pub fn build_mesh(&self) -> Mesh {
+        let mut positions: Vec<[f32; 3]> = Vec::with_capacity(self.heights.len());
+        let mut normals: Vec<[f32; 3]> = Vec::with_capacity(self.heights.len());
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(self.heights.len());
+        let mut indices: Vec<u32> = Vec::new();
+
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                let x = self.origin.x + col as f32 * self.cell_size;
+                let z = self.origin.y + row as f32 * self.cell_size;
+                positions.push([x, self.sample(col, row), z]);
+                normals.push(self.normal_at(x, z).to_array());
+                uvs.push([col as f32 / (self.columns - 1) as f32, row as f32 / (self.rows - 1) as f32]);
+            }
+        }
+
+        for row in 0..self.rows as u32 - 1 {
+            for col in 0..self.columns as u32 - 1 {
+                let top_left = row * self.columns as u32 + col;
+                let bottom_left = top_left + self.columns as u32;
+                indices.extend_from_slice(&[top_left, bottom_left, top_left + 1]);
+                indices.extend_from_slice(&[top_left + 1, bottom_left, bottom_left + 1]);
+            }
+        }
+
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+            .with_inserted_indices(Indices::U32(indices))
+    }
+}
+
+/// Flat ground with a plateau surrounded by ramps
+fn terrain_height(x: f32, z: f32) -> f32 {
+    let ramp = |v: f32, from: f32, to: f32| {
+        let rise = ((v - from + 4.0) / 4.0).clamp(0.0, 1.0);
+        let fall = ((to + 4.0 - v) / 4.0).clamp(0.0, 1.0);
+        rise.min(fall)
+    };
+    3.0 * ramp(x, 5.0, 16.0) * ramp(z, -18.0, -7.0)
+}
+
 fn setup_terrain(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>
+    asset_server: Res<AssetServer>,
+    heightmap: Res<TerrainHeightmap>,
 ) {
     let texture_handle = asset_server.load_with_settings("textures/terrain/mud_cracked_dry_03_diff_1k.png",
     |s: &mut _| {
@@ -51,11 +212,17 @@ fn setup_terrain(
         ..default()
     });
 
+    let overview_material_handle = materials.add(StandardMaterial {
+        base_color: Color::srgb(0.45, 0.4, 0.3),
+        unlit: true,
+        ..default()
+    });
+
     commands.spawn(
         (
             PbrBundle {
-                mesh: meshes.add(Plane3d::default().mesh().size(50.0, 50.0).subdivisions(10)), 
-                material: terrain_material_handle,                
+                mesh: meshes.add(heightmap.build_mesh()),
+                material: terrain_material_handle.clone(),
                 ..default()
             },
             NotShadowCaster,
@@ -63,4 +230,33 @@ fn setup_terrain(
         )
     );
 
-}
\ No newline at end of file
+    commands.insert_resource(TerrainMaterials { detailed: terrain_material_handle, overview: overview_material_handle });
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: Vec3, target: Vec3) -> Ray3d {
+        Ray3d::new(origin, target - origin)
+    }
+
+    #[test]
+    fn raycast_hits_flat_ground() {
+        let heightmap = TerrainHeightmap::from_fn(TERRAIN_SIZE, TERRAIN_CELL_SIZE, |_, _| 0.0);
+        let hit = heightmap.raycast(ray(Vec3::new(0.0, 20.0, 20.0), Vec3::new(4.0, 0.0, -3.0))).unwrap();
+        assert!(hit.abs_diff_eq(Vec3::new(4.0, 0.0, -3.0), 1e-3));
+        assert_eq!(heightmap.raycast(ray(Vec3::new(0.0, 20.0, 20.0), Vec3::new(0.0, 25.0, 0.0))), None);
+    }
+
+    #[test]
+    fn raycast_hits_the_plateau_before_the_plane_below() {
+        let heightmap = TerrainHeightmap::from_fn(TERRAIN_SIZE, TERRAIN_CELL_SIZE, terrain_height);
+        // Low angle ray aiming at the plateau top, it would reach y = 0 far behind it
+        let target = Vec3::new(10.0, 3.0, -12.0);
+        let hit = heightmap.raycast(ray(Vec3::new(10.0, 9.0, 2.0), target)).unwrap();
+        assert!(hit.abs_diff_eq(target, 1e-2), "{hit}");
+        assert!((hit.y - heightmap.height_at(hit.x, hit.z)).abs() < 1e-3);
+    }
+}
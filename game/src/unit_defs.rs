@@ -0,0 +1,102 @@
+use bevy::{asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext}, prelude::*};
+use serde::Deserialize;
+
+/// Definitions shipped with the game, paths are relative to the assets folder
+pub const T72_DEF: &str = "units/t72.unit.json";
+pub const TANK_DEF: &str = "units/tank.unit.json";
+/// Definitions the player can spawn, see [`crate::units::SpawnChoice`]
+pub const SPAWNABLE_DEFS: [&str; 2] = [T72_DEF, TANK_DEF];
+
+pub struct MyUnitDefsPlugin;
+
+impl Plugin for MyUnitDefsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<UnitDef>()
+            .init_asset_loader::<UnitDefLoader>();
+    }
+}
+
+/// Everything needed to spawn a unit of some kind, loaded from `*.unit.json` files
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct UnitDef {
+    pub name: String,
+    /// glTF file relative to the assets folder
+    pub model: String,
+    /// Half of the unit's footprint, used for picking and spacing units
+    pub radius: f32,
+    /// Max speed, units per second
+    pub speed: f32,
+    /// Units per second squared
+    pub acceleration: f32,
+    /// Units per second squared
+    pub deceleration: f32,
+    /// Radians per second
+    pub turn_rate: f32,
+    /// See [`crate::units::VehicleKinematics::turn_in_place_angle`]
+    pub turn_in_place_angle: f32,
+    pub health: f32,
+    #[serde(default)]
+    pub weapons: Vec<WeaponDef>,
+    pub cost: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WeaponDef {
+    pub name: String,
+    pub damage: f32,
+    pub range: f32,
+    /// Seconds between shots
+    pub reload_time: f32,
+}
+
+#[derive(Default)]
+struct UnitDefLoader;
+
+impl AssetLoader for UnitDefLoader {
+    type Asset = UnitDef;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<UnitDef, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["unit.json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn shipped_definitions_parse() {
+        let units_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/units");
+        let mut parsed = 0;
+        for entry in std::fs::read_dir(&units_dir).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.to_string_lossy().ends_with(".unit.json") {
+                continue;
+            }
+            let def: UnitDef = serde_json::from_slice(&std::fs::read(&path).unwrap())
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            assert!(units_dir.parent().unwrap().join(&def.model).exists(), "{}: missing model {}", path.display(), def.model);
+            parsed += 1;
+        }
+        assert!(parsed >= SPAWNABLE_DEFS.len());
+        for def in SPAWNABLE_DEFS {
+            assert!(units_dir.parent().unwrap().join(def).exists(), "missing {}", def);
+        }
+    }
+}
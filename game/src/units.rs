@@ -1,74 +1,271 @@
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::{asset::LoadState, ecs::entity::EntityHashSet, math::bounding::Aabb3d, prelude::*, window::{CursorIcon, PrimaryWindow}};
 use bevy_panorbit_camera::PanOrbitCamera;
-use parry2d::bounding_volume::BoundingSphere;
 
-use crate::{terrain::MyGroundPlane, util::projection::project_on_terrain};
+use crate::{economy::ResourceNode, formation::GroupMovementMode, orders::{is_queue_modifier_pressed, OrderKind, OrderQueue, OrderTarget, OrderTargeting, UnitOrder}, terrain::TerrainHeightmap, unit_defs::{UnitDef, SPAWNABLE_DEFS, T72_DEF}, util::{picking::pick_entity, projection::project_on_terrain}};
 
 pub struct MyUnitsPlugin;
 
 impl Plugin for MyUnitsPlugin {
     fn build(&self, app: &mut App) {
         app
-            .init_resource::<MyGroundCoords>()
+            .init_resource::<SpawnChoice>()
+            .add_event::<SpawnUnit>()
             .add_systems(Startup, setup_units)
-            .add_systems(Update, spawn_tank)
+            .add_systems(Update, cycle_spawn_choice)
+            .add_systems(Update, spawn_tank.after(cycle_spawn_choice))
+            .add_systems(Update, spawn_requested_units.after(spawn_tank))
             .add_systems(Update, send_selected_units)
-            .add_systems(Update, move_units);
+            .add_systems(Update, update_cursor_icon)
+            .add_systems(Update, move_units)
+            .add_systems(Update, follow_terrain.after(move_units));
     }
 }
 
+/// Currently selected entities in the order they were selected.
+/// Every one of them also gets the [`Selected`] marker, which is kept in sync once per frame.
 #[derive(Resource, Default, Debug)]
 pub struct SelectedUnits {
-    pub unit_entities: Vec<Entity>,
+    entities: Vec<Entity>,
+    members: EntityHashSet,
+    /// Set while an enemy or neutral entity is inspected, such a selection can't be given orders
+    read_only: bool,
 }
 
-#[derive(Resource, Default)]
-struct MyGroundCoords {
-    global: Vec3,
-    local: Vec2,
+impl SelectedUnits {
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    /// Selected entities that accept orders from the local player
+    pub fn commandable(&self) -> &[Entity] {
+        if self.read_only {
+            &[]
+        } else {
+            &self.entities
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.members.contains(&entity)
+    }
+
+    /// Replaces the whole selection
+    pub fn set(&mut self, entities: &[Entity], read_only: bool) {
+        self.clear();
+        for entity in entities {
+            self.insert(*entity);
+        }
+        self.read_only = read_only && !self.entities.is_empty();
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.members.clear();
+        self.read_only = false;
+    }
+
+    /// Appends the entity to the end of the selection, returns false if it was already selected
+    pub fn insert(&mut self, entity: Entity) -> bool {
+        if !self.members.insert(entity) {
+            return false;
+        }
+        self.entities.push(entity);
+        true
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        if !self.members.remove(&entity) {
+            return false;
+        }
+        self.entities.retain(|e| *e != entity);
+        if self.entities.is_empty() {
+            self.read_only = false;
+        }
+        true
+    }
 }
 
+/// Marker for entities in [`SelectedUnits`], handy for filtering queries
+#[derive(Component, Debug, Default)]
+pub struct Selected;
+
+/// Distance to the destination at which a unit is considered arrived
+const ARRIVAL_DISTANCE: f32 = 0.1;
+/// How quickly units tilt towards the terrain slope, the higher the faster
+const TILT_SMOOTHNESS: f32 = 8.0;
+
 #[derive(Component, Default)]
 pub struct MovableUnit {
     pub half_size: f32,
+    /// Max speed
     pub speed: f32,
+    pub current_speed: f32,
     pub destination: Option<Vec3>,
+    /// Point the unit turns to while standing, e.g. an enemy it aims at
+    pub face_towards: Option<Vec3>,
 }
 
-fn setup_units(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>
-) {
+impl MovableUnit {
+    /// Box around the unit standing at the given point, used for picking it with the mouse
+    pub fn bounds(&self, translation: Vec3) -> Aabb3d {
+        Aabb3d::new(translation + Vec3::Y * self.half_size * 0.5, Vec3::new(self.half_size, self.half_size * 0.5, self.half_size))
+    }
+}
 
+/// Player the unit belongs to
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Owner(pub u8);
+
+/// Identifies the kind of unit, e.g. to select all units of the same type
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct UnitType(pub String);
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn full(max: f32) -> Health {
+        Health { current: max, max }
+    }
+}
+
+/// Child entity holding the unit's 3D model, so the looks can change without touching the unit itself
+#[derive(Component)]
+pub struct UnitModel;
+
+/// Player sitting at this computer
+pub const LOCAL_PLAYER: Owner = Owner(0);
+
+/// How a vehicle accelerates, brakes and turns.
+#[derive(Component, Debug, Clone)]
+pub struct VehicleKinematics {
+    /// Units per second squared
+    pub acceleration: f32,
+    /// Units per second squared
+    pub deceleration: f32,
+    /// Radians per second
+    pub turn_rate: f32,
+    /// If the destination is further than this angle (radians) from the heading,
+    /// the vehicle stops and turns in place before driving on, like tracked vehicles do.
+    pub turn_in_place_angle: f32,
+}
+
+/// Asks for a unit to be spawned once its definition is loaded
+#[derive(Event, Debug, Clone)]
+pub struct SpawnUnit {
+    pub def: Handle<UnitDef>,
+    pub position: Vec3,
+    pub owner: Owner,
+}
+
+/// Builds a unit entity from its definition
+pub fn spawn_unit(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    def_handle: Handle<UnitDef>,
+    def: &UnitDef,
+    position: Vec3,
+    owner: Owner,
+) -> Entity {
     commands.spawn(
         (
-            SceneBundle {
-                scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset("models/units/T72.glb")),
-                ..default()
+            SpatialBundle::from_transform(Transform::from_translation(position)),
+            MovableUnit { half_size: def.radius, speed: def.speed, ..default() },
+            VehicleKinematics {
+                acceleration: def.acceleration,
+                deceleration: def.deceleration,
+                turn_rate: def.turn_rate,
+                turn_in_place_angle: def.turn_in_place_angle,
             },
-            MovableUnit { half_size: 4.0, speed: 5.0, destination: None }
+            OrderQueue::default(),
+            owner,
+            UnitType(def.name.clone()),
+            Health::full(def.health),
+            def_handle,
         )
-    );
+    ).with_children(|parent| {
+        parent.spawn(
+            (
+                SceneBundle {
+                    scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset(def.model.clone())),
+                    ..default()
+                },
+                UnitModel,
+            )
+        );
+    }).id()
+}
+
+fn setup_units(
+    asset_server: Res<AssetServer>,
+    mut spawn_writer: EventWriter<SpawnUnit>,
+) {
+    spawn_writer.send(SpawnUnit { def: asset_server.load(T72_DEF), position: Vec3::ZERO, owner: LOCAL_PLAYER });
+}
+
+/// Spawns the requested units, waiting for their definitions to load
+fn spawn_requested_units(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    unit_defs: Res<Assets<UnitDef>>,
+    mut reader: EventReader<SpawnUnit>,
+    mut pending: Local<Vec<SpawnUnit>>,
+) {
+    pending.extend(reader.read().cloned());
+    pending.retain(|request| {
+        if let Some(def) = unit_defs.get(&request.def) {
+            spawn_unit(&mut commands, &asset_server, request.def.clone(), def, request.position, request.owner);
+            return false;
+        }
+        if let Some(LoadState::Failed(error)) = asset_server.get_load_state(&request.def) {
+            warn!("Can't spawn a unit without its definition: {}", error);
+            return false;
+        }
+        true
+    });
+}
+
+/// Which of [`SPAWNABLE_DEFS`] Space spawns, Tab switches to the next one
+#[derive(Resource, Default, Debug)]
+pub struct SpawnChoice {
+    pub index: usize,
+}
+
+fn cycle_spawn_choice(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut choice: ResMut<SpawnChoice>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        choice.index = (choice.index + 1) % SPAWNABLE_DEFS.len();
+        info!("Spawning {}", SPAWNABLE_DEFS[choice.index]);
+    }
 }
 
 // Taken from: https://bevy-cheatbook.github.io/cookbook/cursor2world.html#3d-games
 fn spawn_tank(
-    mut commands: Commands,
-    mut mycoords: ResMut<MyGroundCoords>,
-    mouse_input: Res<ButtonInput<MouseButton>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>, // MyGameCamera
-    q_plane: Query<&GlobalTransform, With<MyGroundPlane>>,
-    asset_server: Res<AssetServer>
+    heightmap: Res<TerrainHeightmap>,
+    choice: Res<SpawnChoice>,
+    asset_server: Res<AssetServer>,
+    mut spawn_writer: EventWriter<SpawnUnit>,
 ) {
     if !keyboard_input.just_pressed(KeyCode::Space) {
         return;
     }
+    // Shift+Space spawns the unit for the enemy
+    let owner = if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) { Owner(1) } else { LOCAL_PLAYER };
+    let def_path = SPAWNABLE_DEFS[choice.index];
     let (camera, camera_transform) = q_camera.single();
 
-    let ground_transform = q_plane.single();
-
     let window = q_window.single();
 
     // check if the cursor is inside the window and get its position
@@ -76,51 +273,33 @@ fn spawn_tank(
         return;
     };
 
-    let Some(global_cursor ) = project_on_terrain(cursor_position, &camera, &camera_transform, &ground_transform) else {
+    let Some(global_cursor ) = project_on_terrain(cursor_position, camera, camera_transform, &heightmap) else {
         return;
     };
 
-    mycoords.global = global_cursor;
-    eprintln!("\nGlobal cursor coords: {}/{}/{}", global_cursor.x, global_cursor.y, global_cursor.z);
-
-    commands.spawn(
-        (
-            SceneBundle {
-                scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset("models/units/T72.glb")),
-                transform: Transform {
-                    translation: Vec3 { x: global_cursor.x, y: global_cursor.y, z: global_cursor.z }, rotation: Quat::IDENTITY, scale: Vec3::ONE
-                },
-                ..default()
-            },
-            MovableUnit { half_size: 4.0, speed: 5.0, destination: None },
-        )
-    );
-
-    // to compute the local coordinates, we need the inverse of the plane's transform
-    let inverse_transform_matrix = ground_transform.compute_matrix().inverse();
-    let local_cursor = inverse_transform_matrix.transform_point3(global_cursor);
-
-    // we can discard the Y coordinate, because it should always be zero (our point is supposed to be on the plane)
-    mycoords.local = local_cursor.xz();
-    //eprintln!("Local cursor coords: {}/{}", local_cursor.x, local_cursor.z);
+    spawn_writer.send(SpawnUnit { def: asset_server.load(def_path), position: global_cursor, owner });
 }
 
 
+/// Right click orders the selected units to act on whatever is under the cursor
+#[allow(clippy::too_many_arguments)]
 fn send_selected_units(
-    selected_units: ResMut<SelectedUnits>,
+    selected_units: Res<SelectedUnits>,
+    group_mode: Res<GroupMovementMode>,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
-    q_plane: Query<&GlobalTransform, With<MyGroundPlane>>,
-    mut units_q: Query<(&mut MovableUnit, Entity)>,
+    heightmap: Res<TerrainHeightmap>,
+    units_q: Query<(Entity, &Transform, &MovableUnit, &Owner)>,
+    nodes_q: Query<(Entity, &Transform, &ResourceNode)>,
+    mut orders_writer: EventWriter<UnitOrder>,
 ) {
-    if !mouse_input.just_pressed(MouseButton::Right) {
+    if !mouse_input.just_pressed(MouseButton::Right) || selected_units.commandable().is_empty() {
         return;
     }
     let (camera, camera_transform) = q_camera.single();
 
-    let ground_transform = q_plane.single();
-
     let window = q_window.single();
 
     // check if the cursor is inside the window and get its position
@@ -128,30 +307,164 @@ fn send_selected_units(
         return;
     };
 
-    let Some(global_cursor ) = project_on_terrain(cursor_position, &camera, &camera_transform, &ground_transform) else {
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
+        return;
+    };
+    let Some(global_cursor ) = project_on_terrain(cursor_position, camera, camera_transform, &heightmap) else {
         return;
     };
 
-    // Move units to point
-    for (mut unit, entity) in units_q.iter_mut() {
-        if selected_units.unit_entities.contains(&entity) {
-            unit.destination = Some(global_cursor);
+    let (kind, target) = match context_order(ray, global_cursor, &units_q, &nodes_q) {
+        (OrderKind::Move, target) if group_mode.enabled => (OrderKind::GroupMove, target),
+        order => order,
+    };
+
+    orders_writer.send(UnitOrder {
+        units: selected_units.commandable().to_vec(),
+        kind,
+        target,
+        queued: is_queue_modifier_pressed(&keyboard_input),
+    });
+}
+
+/// Shows what a right click would do with the selected units
+fn update_cursor_icon(
+    selected_units: Res<SelectedUnits>,
+    targeting: Res<OrderTargeting>,
+    mut q_window: Query<&mut Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    heightmap: Res<TerrainHeightmap>,
+    units_q: Query<(Entity, &Transform, &MovableUnit, &Owner)>,
+    nodes_q: Query<(Entity, &Transform, &ResourceNode)>,
+) {
+    let mut window = q_window.single_mut();
+
+    let icon = if targeting.0.is_some() {
+        CursorIcon::Crosshair
+    } else if selected_units.commandable().is_empty() {
+        CursorIcon::Default
+    } else {
+        let (camera, camera_transform) = q_camera.single();
+        let context = window.cursor_position().and_then(|cursor_position| {
+            let ray = camera.viewport_to_world(camera_transform, cursor_position)?;
+            let global_cursor = project_on_terrain(cursor_position, camera, camera_transform, &heightmap)?;
+            Some(context_order(ray, global_cursor, &units_q, &nodes_q).0)
+        });
+
+        match context {
+            Some(OrderKind::Attack) => CursorIcon::Crosshair,
+            Some(OrderKind::Follow) => CursorIcon::Pointer,
+            Some(OrderKind::Gather) => CursorIcon::Grab,
+            _ => CursorIcon::Default,
         }
+    };
+
+    // Avoid touching the window every frame
+    if window.cursor.icon != icon {
+        window.cursor.icon = icon;
     }
 }
 
-fn move_units(mut units_q: Query<(&mut Transform, &mut MovableUnit)>, time: Res<Time>) {
-    for (mut tr, mut movable) in units_q.iter_mut() {
-        if let Some(moving_destination) = movable.destination {
+/// What a right click orders the selected units to do:
+/// attack an enemy, follow a friendly unit, gather a resource or just move there.
+///
+/// ## Args:
+/// * `ray` - ray from the camera through the cursor
+/// * `point` - point on the ground under the cursor
+pub fn context_order(
+    ray: Ray3d,
+    point: Vec3,
+    units_q: &Query<(Entity, &Transform, &MovableUnit, &Owner)>,
+    nodes_q: &Query<(Entity, &Transform, &ResourceNode)>,
+) -> (OrderKind, OrderTarget) {
+    let units = units_q.iter().map(|(e, tr, unit, _)| (e, unit.bounds(tr.translation)));
+    if let Some(target) = pick_entity(ray, units) {
+        let is_enemy = units_q.get(target).is_ok_and(|(_, _, _, owner)| *owner != LOCAL_PLAYER);
+        let kind = if is_enemy { OrderKind::Attack } else { OrderKind::Follow };
+        return (kind, OrderTarget::Unit(target));
+    }
 
-            let desired_rotation = tr.looking_at(moving_destination, Vec3::Y);
-            let lerp = tr.rotation.lerp(desired_rotation.rotation, 2.0 * time.delta_seconds());
-            tr.rotation = lerp;
+    let nodes = nodes_q.iter().map(|(e, tr, node)| (e, node.bounds(tr.translation)));
+    if let Some(node) = pick_entity(ray, nodes) {
+        return (OrderKind::Gather, OrderTarget::Unit(node));
+    }
+
+    (OrderKind::Move, OrderTarget::Point(point))
+}
+
+fn move_units(
+    mut units_q: Query<(&mut Transform, &mut MovableUnit, &VehicleKinematics)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_seconds();
+    for (mut tr, mut movable, kinematics) in units_q.iter_mut() {
+        let mut target_speed = 0.0;
+
+        if let Some(moving_destination) = movable.destination {
+            let distance = (moving_destination - tr.translation).with_y(0.0).length();
 
-            tr.translation = tr.translation.move_towards(moving_destination, 5.0 * time.delta_seconds());
-            if tr.translation.distance(moving_destination) < 0.1 {
+            if distance < ARRIVAL_DISTANCE {
                 movable.destination = None;
+            } else {
+                let heading_error = turn_towards(&mut tr, moving_destination, kinematics.turn_rate * dt);
+
+                if heading_error.abs() < kinematics.turn_in_place_angle {
+                    // Limit the speed so the vehicle is able to brake right at the destination
+                    let braking_speed = (2.0 * kinematics.deceleration * distance).sqrt();
+                    target_speed = movable.speed.min(braking_speed);
+                }
             }
+        } else if let Some(face_towards) = movable.face_towards {
+            turn_towards(&mut tr, face_towards, kinematics.turn_rate * dt);
+        }
+
+        movable.current_speed = if movable.current_speed < target_speed {
+            (movable.current_speed + kinematics.acceleration * dt).min(target_speed)
+        } else {
+            (movable.current_speed - kinematics.deceleration * dt).max(target_speed)
+        };
+
+        if movable.current_speed > 0.0 {
+            let forward = tr.forward().with_y(0.0).normalize_or_zero();
+            tr.translation += forward * movable.current_speed * dt;
         }
     }
 }
+
+/// Rotates the unit around Y towards the point, but no more than by `max_turn` radians.
+/// Returns the signed angle between the heading and the point before the rotation.
+fn turn_towards(tr: &mut Transform, point: Vec3, max_turn: f32) -> f32 {
+    let to_point = (point - tr.translation).with_y(0.0);
+    let forward = tr.forward().with_y(0.0).normalize_or_zero();
+    let heading_error = forward.cross(to_point).y.atan2(forward.dot(to_point));
+    tr.rotate_y(heading_error.clamp(-max_turn, max_turn));
+    heading_error
+}
+
+/// Keeps units on the terrain surface and tilts them along the slope they are standing on.
+fn follow_terrain(
+    mut units_q: Query<(&mut Transform, &MovableUnit)>,
+    heightmap: Res<TerrainHeightmap>,
+    time: Res<Time>,
+) {
+    let smoothing = 1.0 - (-TILT_SMOOTHNESS * time.delta_seconds()).exp();
+    for (mut tr, movable) in units_q.iter_mut() {
+        let pos = tr.translation;
+        tr.translation.y = heightmap.height_at(pos.x, pos.z);
+
+        let forward = tr.forward().with_y(0.0).try_normalize().unwrap_or(Vec3::NEG_Z);
+        let right = Vec3::new(-forward.z, 0.0, forward.x);
+
+        // Sample the ground under the hull rather than at a single point,
+        // so small bumps don't make the vehicle shake
+        let reach = movable.half_size * 0.5;
+        let height = |offset: Vec3| heightmap.height_at(pos.x + offset.x, pos.z + offset.z);
+        let along = forward * reach * 2.0 + Vec3::Y * (height(forward * reach) - height(-forward * reach));
+        let across = right * reach * 2.0 + Vec3::Y * (height(right * reach) - height(-right * reach));
+        let normal = across.cross(along).normalize();
+
+        let surface_forward = (forward - normal * forward.dot(normal)).normalize();
+        let target = Transform::IDENTITY.looking_to(surface_forward, normal).rotation;
+        tr.rotation = tr.rotation.slerp(target, smoothing);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod projection;
-pub mod point_2d;
\ No newline at end of file
+pub mod point_2d;
+pub mod picking;
\ No newline at end of file
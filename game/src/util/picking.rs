@@ -0,0 +1,14 @@
+use bevy::{math::bounding::{Aabb3d, RayCast3d}, prelude::*};
+
+/// Finds the closest object hit by the ray.
+///
+/// ## Args:
+/// * `ray` - usually a ray from the camera through the cursor, see [`Camera::viewport_to_world`]
+/// * `entities` - (entity, bounding box) of the objects to check
+pub fn pick_entity(ray: Ray3d, entities: impl Iterator<Item = (Entity, Aabb3d)>) -> Option<Entity> {
+    let ray_cast = RayCast3d::from_ray(ray, f32::MAX);
+    entities
+        .filter_map(|(entity, aabb)| ray_cast.aabb_intersection_at(&aabb).map(|distance| (entity, distance)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(entity, _)| entity)
+}
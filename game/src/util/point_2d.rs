@@ -1,67 +1,290 @@
-use bevy::math::Vec3;
-
-#[derive(Debug, Clone)]
-pub struct Trapez {
-    pub top_left: Vec3,
-    pub top_right: Vec3,
-    pub bottom_left: Vec3,
-    pub bottom_right: Vec3
+use bevy::math::{Rect, Vec2};
+
+/// Tolerance for points lying exactly on an edge
+const EPSILON: f32 = 1e-5;
+
+/// Part of the area of `rect` that lies inside `area`, from 0.0 to 1.0.
+/// A degenerate (zero area) rectangle counts as fully inside if its center is inside the area.
+pub fn rect_overlap_fraction(rect: Rect, area: Rect) -> f32 {
+    let rect_area = rect.width() * rect.height();
+    if rect_area <= 0.0 {
+        return if area.contains(rect.center()) { 1.0 } else { 0.0 };
+    }
+
+    let overlap = rect.intersect(area);
+    if overlap.is_empty() {
+        return 0.0;
+    }
+    overlap.width() * overlap.height() / rect_area
+}
+
+/// Convex polygon on a plane, e.g. a selection area on the screen.
+///
+/// Containment is checked with cross products against every edge, so any edge direction
+/// (including vertical and horizontal ones) and both windings are fine.
+/// Degenerate polygons (a segment or a single point) contain only the points lying on them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvexPolygon {
+    /// Vertices in counter-clockwise order (in a Y-up coordinate system)
+    vertices: Vec<Vec2>,
 }
 
-impl Trapez {
-    pub fn normal_rotation(&self) -> Trapez {
-        if self.top_left.x <= self.top_right.x && self.top_left.z <= self.bottom_left.z {
-            self.clone()
-        } else if self.top_right.x <= self.bottom_right.x && self.top_right.z <= self.top_left.z {
-            // turn left 1
-            Trapez { top_left: self.top_right, top_right: self.bottom_right, bottom_left: self.top_left, bottom_right: self.bottom_left }
-        } else if self.bottom_right.x <= self.bottom_left.x && self.bottom_right.z <= self.top_right.z {
-            // turn left 2
-            Trapez { top_left: self.bottom_right, top_right: self.bottom_left, bottom_left: self.top_right, bottom_right: self.top_left }
-        } else {
-            // turn right 1
-            Trapez { top_left: self.bottom_left, top_right: self.top_left, bottom_left: self.bottom_right, bottom_right: self.top_right }
+impl ConvexPolygon {
+    /// Builds a polygon from vertices going around it in either direction.
+    pub fn new(mut vertices: Vec<Vec2>) -> ConvexPolygon {
+        vertices.dedup_by(|a, b| a.distance_squared(*b) < EPSILON * EPSILON);
+        if vertices.len() > 1 && vertices[0].distance_squared(vertices[vertices.len() - 1]) < EPSILON * EPSILON {
+            vertices.pop();
+        }
+        if signed_area(&vertices) < 0.0 {
+            vertices.reverse();
         }
+        ConvexPolygon { vertices }
     }
 
-    pub fn contains(&self, point: Vec3) -> bool {
-        let n = self.normal_rotation();
+    pub fn from_rect(rect: Rect) -> ConvexPolygon {
+        ConvexPolygon::new(vec![
+            rect.min,
+            Vec2::new(rect.max.x, rect.min.y),
+            rect.max,
+            Vec2::new(rect.min.x, rect.max.y),
+        ])
+    }
 
-        let is_below_upper_line = is_point_below_the_line(point, n.top_left, n.top_right);
-        let is_above_bottom_line = is_point_above_the_line(point, n.bottom_left, n.bottom_right);
-        let is_right_to_left_line = is_point_right_to_the_line(point, n.top_left, n.bottom_left);
-        let is_left_to_right_line = is_point_left_to_the_line(point, n.top_right, n.bottom_right);
+    pub fn vertices(&self) -> &[Vec2] {
+        &self.vertices
+    }
 
-        is_below_upper_line && is_above_bottom_line && is_right_to_left_line && is_left_to_right_line
+    fn edges(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.vertices.iter().zip(self.vertices.iter().cycle().skip(1)).map(|(a, b)| (*a, *b))
     }
-}
 
-fn calc_line_z(x: f32, p1: Vec3, p2: Vec3) -> f32 {
-    (((x - p1.x) * (p2.z - p1.z)) / (p2.x - p1.x)) + p1.z
+    /// Checks if the point is inside the polygon or on its border
+    pub fn contains(&self, point: Vec2) -> bool {
+        if self.vertices.is_empty() {
+            return false;
+        }
+        if signed_area(&self.vertices).abs() <= EPSILON {
+            // A segment or a single point
+            return self.edges().any(|(a, b)| distance_to_segment(point, a, b) <= EPSILON);
+        }
+        self.edges().all(|(a, b)| (b - a).perp_dot(point - a) >= -EPSILON)
+    }
 }
 
-fn calc_line_x(z: f32, p1: Vec3, p2: Vec3) -> f32 {
-    ((z - p1.z) * (p2.x - p1.x) / (p2.z - p1.z)) + p1.x
+/// Arbitrary polygon on a plane, e.g. a lasso drawn on the screen.
+///
+/// May be concave and self-intersecting. A point is inside when the border winds around it
+/// (non-zero winding rule), so loops drawn over each other still count as inside.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Polygon {
+    /// The last vertex is implicitly connected to the first one
+    vertices: Vec<Vec2>,
 }
 
-pub fn is_point_left_to_the_line(point: Vec3, p1: Vec3, p2: Vec3) -> bool {
-    let x_on_the_line = calc_line_x(point.z, p1, p2);
-    point.x < x_on_the_line
-}
+impl Polygon {
+    pub fn new(vertices: Vec<Vec2>) -> Polygon {
+        Polygon { vertices }
+    }
 
-pub fn is_point_right_to_the_line(point: Vec3, p1: Vec3, p2: Vec3) -> bool {
-    let x_on_the_line = calc_line_x(point.z, p1, p2);
-    point.x > x_on_the_line
+    pub fn vertices(&self) -> &[Vec2] {
+        &self.vertices
+    }
+
+    /// Smallest rectangle containing the polygon, empty for a polygon without vertices
+    pub fn bounding_rect(&self) -> Rect {
+        self.vertices.iter().fold(Rect::EMPTY, |rect, vertex| rect.union_point(*vertex))
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.vertices.iter().zip(self.vertices.iter().cycle().skip(1)).map(|(a, b)| (*a, *b))
+    }
+
+    /// How many times the border goes counter-clockwise around the point
+    fn winding_number(&self, point: Vec2) -> i32 {
+        let mut winding = 0;
+        for (a, b) in self.edges() {
+            let side = (b - a).perp_dot(point - a);
+            if a.y <= point.y {
+                if b.y > point.y && side > 0.0 {
+                    winding += 1;
+                }
+            } else if b.y <= point.y && side < 0.0 {
+                winding -= 1;
+            }
+        }
+        winding
+    }
+
+    /// Checks if the point is inside the polygon or on its border
+    pub fn contains(&self, point: Vec2) -> bool {
+        self.winding_number(point) != 0
+            || self.edges().any(|(a, b)| distance_to_segment(point, a, b) <= EPSILON)
+    }
+
+    /// Checks if the circle touches or intersects the polygon
+    pub fn overlaps_circle(&self, center: Vec2, radius: f32) -> bool {
+        self.contains(center) || self.edges().any(|(a, b)| distance_to_segment(center, a, b) <= radius)
+    }
 }
 
-pub fn is_point_above_the_line(point: Vec3, p1: Vec3, p2: Vec3) -> bool {
-    let z_on_the_line = calc_line_z(point.x, p1, p2);
-    point.z < z_on_the_line
+/// Positive for counter-clockwise vertices (in a Y-up coordinate system)
+fn signed_area(vertices: &[Vec2]) -> f32 {
+    vertices.iter().zip(vertices.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum::<f32>() / 2.0
 }
 
-pub fn is_point_below_the_line(point: Vec3, p1: Vec3, p2: Vec3) -> bool {
-    let z_on_the_line = calc_line_z(point.x, p1, p2);
-    point.z > z_on_the_line
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let length_squared = ab.length_squared();
+    if length_squared <= EPSILON * EPSILON {
+        return point.distance(a);
+    }
+    let t = ((point - a).dot(ab) / length_squared).clamp(0.0, 1.0);
+    point.distance(a + ab * t)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> ConvexPolygon {
+        ConvexPolygon::from_rect(Rect::new(0.0, 0.0, 10.0, 10.0))
+    }
+
+    fn rotated(polygon: &ConvexPolygon, angle: f32) -> ConvexPolygon {
+        let rotation = Vec2::from_angle(angle);
+        ConvexPolygon::new(polygon.vertices().iter().map(|v| rotation.rotate(*v)).collect())
+    }
+
+    #[test]
+    fn axis_aligned_square_contains_points() {
+        let polygon = square();
+        assert!(polygon.contains(Vec2::new(5.0, 5.0)));
+        assert!(polygon.contains(Vec2::new(0.0, 5.0)));
+        assert!(polygon.contains(Vec2::new(10.0, 10.0)));
+        assert!(!polygon.contains(Vec2::new(-0.1, 5.0)));
+        assert!(!polygon.contains(Vec2::new(5.0, 10.1)));
+    }
+
+    #[test]
+    fn winding_does_not_matter() {
+        let clockwise = ConvexPolygon::new(vec![
+            Vec2::new(0.0, 0.0), Vec2::new(0.0, 10.0), Vec2::new(10.0, 10.0), Vec2::new(10.0, 0.0),
+        ]);
+        assert!(signed_area(clockwise.vertices()) > 0.0);
+        assert!(clockwise.contains(Vec2::new(2.0, 8.0)));
+    }
+
+    #[test]
+    fn rotated_square_contains_points() {
+        let polygon = rotated(&square(), std::f32::consts::FRAC_PI_4);
+        // After 45° rotation the square stands on its corner at the origin
+        assert!(polygon.contains(Vec2::new(0.0, 7.0)));
+        assert!(polygon.contains(Vec2::new(0.0, 0.0)));
+        assert!(!polygon.contains(Vec2::new(5.0, 1.0)));
+        assert!(!polygon.contains(Vec2::new(-5.0, 1.0)));
+        assert!(!polygon.contains(Vec2::new(0.0, 14.2)));
+    }
+
+    #[test]
+    fn rotated_by_quarter_turns_keeps_vertical_edges() {
+        let polygon = rotated(&square(), std::f32::consts::FRAC_PI_2);
+        assert!(polygon.contains(Vec2::new(-5.0, 5.0)));
+        assert!(!polygon.contains(Vec2::new(5.0, 5.0)));
+    }
+
+    #[test]
+    fn degenerate_segment_contains_only_points_on_it() {
+        let polygon = ConvexPolygon::from_rect(Rect::new(0.0, 0.0, 10.0, 0.0));
+        assert!(polygon.contains(Vec2::new(5.0, 0.0)));
+        assert!(!polygon.contains(Vec2::new(5.0, 0.5)));
+        assert!(!polygon.contains(Vec2::new(11.0, 0.0)));
+    }
+
+    #[test]
+    fn degenerate_point_and_empty_polygons() {
+        let point = ConvexPolygon::from_rect(Rect::new(3.0, 3.0, 3.0, 3.0));
+        assert_eq!(point.vertices().len(), 1);
+        assert!(point.contains(Vec2::new(3.0, 3.0)));
+        assert!(!point.contains(Vec2::new(3.0, 3.1)));
+
+        let empty = ConvexPolygon::new(vec![]);
+        assert!(!empty.contains(Vec2::ZERO));
+    }
 
+    #[test]
+    fn circle_overlaps_edges_and_corners() {
+        let polygon = Polygon::new(square().vertices().to_vec());
+        assert!(polygon.overlaps_circle(Vec2::new(5.0, 5.0), 0.1));
+        assert!(polygon.overlaps_circle(Vec2::new(-1.0, 5.0), 1.0));
+        assert!(!polygon.overlaps_circle(Vec2::new(-1.1, 5.0), 1.0));
+        // Near a corner the distance is to the corner itself, not to the edge lines
+        assert!(polygon.overlaps_circle(Vec2::new(11.0, 11.0), 1.5));
+        assert!(!polygon.overlaps_circle(Vec2::new(11.0, 11.0), 1.4));
+    }
+
+    #[test]
+    fn circle_overlaps_rotated_polygon() {
+        let polygon = Polygon::new(rotated(&square(), 0.3).vertices().to_vec());
+        let outside = rotated(&ConvexPolygon::new(vec![Vec2::new(12.0, 5.0)]), 0.3).vertices()[0];
+        assert!(!polygon.contains(outside));
+        assert!(polygon.overlaps_circle(outside, 2.01));
+        assert!(!polygon.overlaps_circle(outside, 1.99));
+    }
+
+    #[test]
+    fn rect_overlap_fraction_counts_the_shared_area() {
+        let area = Rect::new(0.0, 0.0, 10.0, 10.0);
+        assert_eq!(rect_overlap_fraction(Rect::new(2.0, 2.0, 4.0, 4.0), area), 1.0);
+        assert_eq!(rect_overlap_fraction(Rect::new(8.0, 0.0, 12.0, 2.0), area), 0.5);
+        assert_eq!(rect_overlap_fraction(Rect::new(20.0, 0.0, 22.0, 2.0), area), 0.0);
+        assert_eq!(rect_overlap_fraction(Rect::new(5.0, 5.0, 5.0, 5.0), area), 1.0);
+    }
+
+    fn polygon(points: &[(f32, f32)]) -> Polygon {
+        Polygon::new(points.iter().map(|(x, y)| Vec2::new(*x, *y)).collect())
+    }
+
+    #[test]
+    fn concave_polygon_excludes_the_notch() {
+        // L-shape: the top right quarter of the square is cut out
+        let polygon = polygon(&[(0.0, 0.0), (10.0, 0.0), (10.0, 5.0), (5.0, 5.0), (5.0, 10.0), (0.0, 10.0)]);
+        assert!(polygon.contains(Vec2::new(2.0, 8.0)));
+        assert!(polygon.contains(Vec2::new(8.0, 2.0)));
+        assert!(!polygon.contains(Vec2::new(8.0, 8.0)));
+        assert!(polygon.overlaps_circle(Vec2::new(8.0, 8.0), 3.0));
+        assert!(!polygon.overlaps_circle(Vec2::new(8.0, 8.0), 2.9));
+    }
+
+    #[test]
+    fn self_intersecting_figure_eight_contains_both_loops() {
+        // The loops are wound in opposite directions
+        let polygon = polygon(&[(0.0, 0.0), (10.0, 10.0), (10.0, 0.0), (0.0, 10.0)]);
+        assert!(polygon.contains(Vec2::new(1.0, 5.0)));
+        assert!(polygon.contains(Vec2::new(9.0, 5.0)));
+        assert!(!polygon.contains(Vec2::new(5.0, 1.0)));
+        assert!(!polygon.contains(Vec2::new(5.0, 9.0)));
+    }
+
+    #[test]
+    fn area_circled_twice_is_inside() {
+        let polygon = polygon(&[
+            (0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0),
+            (1.0, 1.0), (9.0, 1.0), (9.0, 9.0), (1.0, 9.0),
+        ]);
+        assert!(polygon.contains(Vec2::new(5.0, 5.0)));
+        assert!(polygon.contains(Vec2::new(5.0, 0.5)));
+        assert!(!polygon.contains(Vec2::new(11.0, 5.0)));
+    }
+
+    #[test]
+    fn open_path_is_closed_implicitly() {
+        let polygon = polygon(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]);
+        assert!(polygon.contains(Vec2::new(8.0, 2.0)));
+        assert!(polygon.contains(Vec2::new(5.0, 5.0)));
+        assert!(!polygon.contains(Vec2::new(2.0, 8.0)));
+        assert!(!Polygon::default().contains(Vec2::ZERO));
+    }
+}
@@ -1,55 +1,80 @@
-use bevy::prelude::*;
+use bevy::{math::bounding::{Aabb3d, BoundingVolume}, prelude::*};
 
-/// Finds clobal coordinates of cursor position on the plane that corresponds to the terrain.
+use crate::terrain::TerrainHeightmap;
+
+/// Finds global coordinates of the terrain surface point under the cursor.
 /// 
 /// ## Args:
 /// * `cursor_position` - cursor position on the window
 /// * `camera` - camera object
 /// * `camera_transform` - camera global transformation
-/// * `ground_transform` - terrain global transformation
+/// * `heightmap` - terrain surface
 /// 
 /// ## Usage example
 /// ```
 /// fn my_system(
 ///     q_window: Query<&Window, With<PrimaryWindow>>,
 ///     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>, // MyGameCamera
-///     q_plane: Query<&GlobalTransform, With<MyGroundPlane>>,
+///     heightmap: Res<TerrainHeightmap>,
 /// ) {
 ///     if !mouse_input.just_pressed(MouseButton::Right) {
 ///         return;
 ///     }
 ///     let (camera, camera_transform) = q_camera.single();
 /// 
-///     let ground_transform = q_plane.single();
-/// 
 ///     let window = q_window.single();
 /// 
 ///     // check if the cursor is inside the window and get its position
 ///     let Some(cursor_position) = window.cursor_position() else {
 ///         return;
 ///     };
-///     let Some(point) = project_on_terrain(&cursor_position, &camera, &camera_transform, &ground_transform);
+///     let Some(point) = project_on_terrain(cursor_position, camera, camera_transform, &heightmap);
 /// ```
 pub fn project_on_terrain(
     cursor_position: Vec2,
     camera: &Camera,
     camera_transform: &GlobalTransform,
-    ground_transform: &GlobalTransform,
+    heightmap: &TerrainHeightmap,
 ) -> Option<Vec3> {
-    let plane_origin = ground_transform.translation();
-    let plane = InfinitePlane3d {
-        normal: ground_transform.up(),
-    };
+    let ray = camera.viewport_to_world(camera_transform, cursor_position)?;
 
-    let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
-        return None;
-    };
+    heightmap.raycast(ray)
+}
 
-    let Some(distance) = ray.intersect_plane(plane_origin, plane) else {
-        return None;
+/// Checks whether the point is visible in the camera's viewport.
+pub fn is_in_viewport(point: Vec3, camera: &Camera, camera_transform: &GlobalTransform) -> bool {
+    let Some(viewport_size) = camera.logical_viewport_size() else {
+        return false;
     };
+    camera.world_to_viewport(camera_transform, point)
+        .is_some_and(|p| Rect::from_corners(Vec2::ZERO, viewport_size).contains(p))
+}
+
+/// Projects the bounding box into the viewport and returns the screen rectangle around it.
+/// Corners behind the camera are ignored, `None` if none of them is in front of the camera.
+pub fn project_bounds_to_viewport(bounds: &Aabb3d, camera: &Camera, camera_transform: &GlobalTransform) -> Option<Rect> {
+    let center = Vec3::from(bounds.center());
+    let half_size = Vec3::from(bounds.half_size());
 
-    let global_cursor = ray.get_point(distance);
+    let mut screen_bounds: Option<Rect> = None;
+    for corner in [
+        Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, -1.0, -1.0), Vec3::new(-1.0, 1.0, -1.0), Vec3::new(1.0, 1.0, -1.0),
+        Vec3::new(-1.0, -1.0, 1.0), Vec3::new(1.0, -1.0, 1.0), Vec3::new(-1.0, 1.0, 1.0), Vec3::new(1.0, 1.0, 1.0),
+    ] {
+        if let Some(p) = camera.world_to_viewport(camera_transform, center + corner * half_size) {
+            screen_bounds = Some(match screen_bounds {
+                Some(rect) => rect.union_point(p),
+                None => Rect::from_corners(p, p),
+            });
+        }
+    }
+    screen_bounds
+}
 
-    Some(global_cursor)
-}
\ No newline at end of file
+/// Projects a horizontal circle (e.g. a unit footprint) into the viewport.
+/// Returns the screen position of the center and the screen radius, measured across the view.
+pub fn project_circle_to_viewport(center: Vec3, radius: f32, camera: &Camera, camera_transform: &GlobalTransform) -> Option<(Vec2, f32)> {
+    let screen_center = camera.world_to_viewport(camera_transform, center)?;
+    let side = camera.world_to_viewport(camera_transform, center + camera_transform.right() * radius)?;
+    Some((screen_center, screen_center.distance(side)))
+}